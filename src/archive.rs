@@ -0,0 +1,158 @@
+// Our own copy of every post the sniffer has seen, so we can dig through it later
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::persist::write_atomic;
+use crate::reddit::SnifferPost;
+
+/// A json lines file with a post on each line, and all of it kept in memory for searching.
+/// Clones share the same posts and file
+#[derive(Clone)]
+pub struct Archive {
+    path: PathBuf,
+    posts: Arc<Mutex<Vec<SnifferPost>>>,
+    // Lines in the file that a newer line for the same post has replaced
+    stale: Arc<Mutex<usize>>,
+}
+
+impl Archive {
+    /// Load the archive from disk, starting empty if it isn't there yet
+    pub fn open(path: PathBuf) -> Archive {
+        let mut posts = Vec::<SnifferPost>::new();
+        let mut stale = 0;
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                // Where each post ended up in our list, by fullname
+                let mut index = HashMap::<String, usize>::new();
+                for (i, line) in contents.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str::<SnifferPost>(line) {
+                        // A post shows up again when it's changed, the latest line wins
+                        Ok(post) => match index.get(&post.fullname()) {
+                            Some(&existing) => {
+                                posts[existing] = post;
+                                stale += 1;
+                            }
+                            None => {
+                                index.insert(post.fullname(), posts.len());
+                                posts.push(post);
+                            }
+                        },
+                        Err(e) => error!("Skipping bad line {} in archive {}: {}", i + 1, path.display(), e),
+                    }
+                }
+                warn!("Loaded {} archived posts from {}", posts.len(), path.display());
+            }
+            Err(e) => {
+                warn!("No archive at {} ({}), starting a new one", path.display(), e);
+            }
+        }
+        let archive = Archive {
+            path: path,
+            posts: Arc::new(Mutex::new(posts)),
+            stale: Arc::new(Mutex::new(stale)),
+        };
+        if stale > 0 {
+            archive.compact(&archive.posts.lock().unwrap());
+        }
+        archive
+    }
+
+    /// Save a post, or the new version of one we already have
+    pub fn record(&self, post: &SnifferPost) {
+        let mut posts = self.posts.lock().unwrap();
+        let mut stale = self.stale.lock().unwrap();
+        match posts.iter_mut().find(|p| *p == post) {
+            Some(existing) => {
+                *existing = post.clone();
+                *stale += 1;
+            }
+            None => posts.push(post.clone()),
+        }
+        // Posts get recorded again every time they're sampled, so once old versions make up
+        // most of the file, write it out fresh instead of letting it keep growing
+        if *stale > posts.len() {
+            *stale = 0;
+            self.compact(&posts);
+        }
+        else if let Err(e) = self.append(post) {
+            error!("Failed to write post {} to archive {}: {}", post.id, self.path.display(), e);
+        }
+    }
+
+    // Rewrite the file with only the latest version of each post
+    fn compact(&self, posts: &[SnifferPost]) {
+        let mut contents = String::new();
+        for post in posts {
+            match serde_json::to_string(post) {
+                Ok(line) => {
+                    contents.push_str(line.as_str());
+                    contents.push('\n');
+                }
+                Err(e) => error!("Failed to write post {} to archive {}: {}", post.id, self.path.display(), e),
+            }
+        }
+        match write_atomic(&self.path, contents) {
+            Ok(_) => warn!("Compacted archive {} down to {} posts", self.path.display(), posts.len()),
+            Err(e) => error!("Failed to compact archive {}: {}", self.path.display(), e),
+        }
+    }
+
+    fn append(&self, post: &SnifferPost) -> Result<(), String> {
+        let line = serde_json::to_string(post).map_err(|e| e.to_string())?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).map_err(|e| e.to_string())?;
+        writeln!(file, "{}", line).map_err(|e| e.to_string())
+    }
+
+    /// Every post containing all of the given terms somewhere, newest first
+    pub fn search(&self, terms: &[String]) -> Vec<SnifferPost> {
+        let terms: Vec<String> = terms.iter().map(|t| t.to_lowercase()).collect();
+        self.find(|post| {
+            let text = format!(
+                "{} {} {} {}",
+                post.title, post.body.as_deref().unwrap_or_default(), post.author, post.subreddit
+            ).to_lowercase();
+            terms.iter().all(|t| text.contains(t.as_str()))
+        })
+    }
+
+    /// Everything a user posted between two unix timestamps, newest first
+    pub fn by_user(&self, user: &str, from: u64, to: u64) -> Vec<SnifferPost> {
+        self.find(|post| {
+            post.author.eq_ignore_ascii_case(user) && post.timestamp >= from && post.timestamp <= to
+        })
+    }
+
+    /// Look a post up by its id, fullname, or link
+    pub fn get(&self, id: &str) -> Option<SnifferPost> {
+        // Links look like /r/sub/comments/<id>/title/
+        let id = match id.split("/comments/").nth(1) {
+            Some(rest) => rest.split('/').next().unwrap_or_default(),
+            None => id.trim_start_matches("t3_").trim_start_matches("t1_"),
+        };
+        self.posts.lock().unwrap().iter().find(|p| p.id == id).cloned()
+    }
+
+    /// Everything posted since a unix timestamp, newest first
+    pub fn since(&self, from: u64) -> Vec<SnifferPost> {
+        self.find(|post| post.timestamp >= from)
+    }
+
+    /// The newest few posts
+    pub fn latest(&self, count: usize) -> Vec<SnifferPost> {
+        let mut latest = self.find(|_| true);
+        latest.truncate(count);
+        latest
+    }
+
+    fn find<F: Fn(&SnifferPost) -> bool>(&self, matches: F) -> Vec<SnifferPost> {
+        let mut found: Vec<SnifferPost> = self.posts.lock().unwrap().iter().filter(|p| matches(p)).cloned().collect();
+        found.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        found
+    }
+}
@@ -1,3 +1,6 @@
 // File for module exports
 
-pub mod player;
\ No newline at end of file
+pub mod player;
+pub mod settings;
+pub mod playlist;
+pub mod search;
\ No newline at end of file
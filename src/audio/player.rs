@@ -1,11 +1,13 @@
 use std::sync::{Arc};
-use tokio::sync::{Mutex};
+use std::collections::VecDeque;
+use tokio::sync::{Mutex, mpsc};
+use futures::stream::{self, StreamExt};
 
 use songbird::{
     {Songbird, Call},
     //{ytdl, ytdl_search, tracks::create_player},
-    input::{ytdl, ytdl_search},
-    tracks::{Track, PlayMode, create_player},
+    input::{Input, Restartable},
+    tracks::{Track, TrackQueue, PlayMode, create_player},
     driver::Bitrate,
     Event,
     EventContext,
@@ -19,6 +21,7 @@ use songbird::{
 
 use serenity::{
     CacheAndHttp,
+    http::Http,
     prelude::*,
     async_trait,
     model::{id::{ChannelId}},
@@ -28,6 +31,8 @@ use serenity::{
 use uuid::Uuid;
 use crate::commands::HELP_TEXT;
 use crate::commands::Token;
+use crate::audio::settings::{AudioSettings, MAX_VOLUME};
+use crate::audio::playlist;
 
 // macro to break our tokio lock out of async
 macro_rules! lock_call {
@@ -55,6 +60,44 @@ enum TrackEndAction {
     TIMEOUT,
 }
 
+/// Where a seek's time is counted from
+#[derive(Clone, Debug)]
+pub enum SeekFrom {
+    Start,
+    Forward,
+    Back,
+}
+
+// How wide the now playing bar is
+const PROGRESS_BAR_WIDTH: usize = 20;
+// Don't let one playlist take over the queue
+const MAX_PLAYLIST_ENTRIES: usize = 50;
+// How many yt-dlps we'll have going at once when loading queued tracks
+const LOAD_CONCURRENCY: usize = 4;
+
+/// Something asked to be queued that we haven't loaded yet
+#[derive(Clone, Debug)]
+struct TrackRequest {
+    id: Uuid,
+    url: String,
+    // Filled in when we already know it, like from a playlist
+    title: Option<String>,
+}
+
+impl TrackRequest {
+    fn new(url: String, title: Option<String>) -> TrackRequest {
+        TrackRequest {
+            id: Uuid::new_v4(),
+            url: url,
+            title: title,
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.title.as_deref().unwrap_or(self.url.as_str())
+    }
+}
+
 #[derive(Clone)]
 pub struct AudioPlayer {
     call_handle_lock: Option<Arc<Mutex<Call>>>,
@@ -64,11 +107,17 @@ pub struct AudioPlayer {
     timeout_handle: Option<Arc<Mutex<tokio::task::JoinHandle<()>>>>,
     cache_and_http: Option<std::sync::Arc<CacheAndHttp>>,
     pub audio_text_channel: ChannelId,
+    guild_id: u64,
+    settings: AudioSettings,
+    // Queued tracks still being loaded, in the order they'll go after the call's queue
+    pending: Arc<std::sync::Mutex<VecDeque<TrackRequest>>>,
+    // Where queued requests go to get loaded, one batch at a time so they stay in order
+    loader: Option<mpsc::UnboundedSender<Vec<TrackRequest>>>,
 }
 
 
 impl AudioPlayer {
-    pub async fn new(audio_channel: u64, queue_size: usize, timeout: std::time::Duration) -> Arc<Mutex<AudioPlayer>> {
+    pub async fn new(audio_channel: u64, queue_size: usize, timeout: std::time::Duration, settings: AudioSettings) -> Arc<Mutex<AudioPlayer>> {
         // The actual player object
         let player = Arc::new(Mutex::new(AudioPlayer {
             call_handle_lock: None,
@@ -80,6 +129,10 @@ impl AudioPlayer {
             timeout_handle: None,
             cache_and_http: None,
             audio_text_channel: ChannelId(audio_channel),
+            guild_id: 0, // Filled in when we init
+            settings: settings,
+            pending: Arc::new(std::sync::Mutex::new(VecDeque::new())),
+            loader: None,
         }));
         
         // Get the lock on our player so we can modify it
@@ -107,6 +160,7 @@ impl AudioPlayer {
             })
         });
         self.songbird.initialise_client_data(shard_count, bot_user_id);
+        self.guild_id = guild_id_u64;
         let guild_id = songbird::id::GuildId::from(guild_id_u64);
 
         warn!("Trying to create call for guild ID: {}", guild_id);
@@ -121,6 +175,11 @@ impl AudioPlayer {
             // as the call actually persists, even if we call leave()
             self.idle_callback_struct.as_ref().unwrap().clone(),
         );
+        // Get the next track ready whenever one starts
+        call.add_global_event(
+            Event::Track(TrackEvent::Play),
+            PrefetchCallback { call_lock: call_lock.clone() },
+        );
         // Add the callback to client disconnect event
         call.add_global_event(
             Event::Core(CoreEvent::ClientDisconnect),
@@ -128,6 +187,19 @@ impl AudioPlayer {
         );
         warn!("Installed track end event and callback");
         warn!("Created call for guild {}", guild_id);
+
+        // Everything queued gets loaded by this one task, for as long as we're around
+        let (loader, batches) = mpsc::unbounded_channel();
+        self.loader = Some(loader);
+        tokio::spawn(run_loader(batches, Loader {
+            settings: self.settings.clone(),
+            guild_id: guild_id_u64,
+            call_lock: call_lock.clone(),
+            pending: self.pending.clone(),
+            channel: self.audio_text_channel,
+            http: cache_and_http.http.clone(),
+            idle_callback: self.idle_callback_struct.as_ref().unwrap().clone(),
+        }));
     }
 
 
@@ -195,6 +267,7 @@ impl AudioPlayer {
         self.stop(&mut call)
     }
     fn stop(&self, call: &mut Call) -> Result<(), String> {
+        self.pending.lock().unwrap().clear();
         call.queue().stop();
         Ok(())
     }
@@ -220,7 +293,8 @@ impl AudioPlayer {
         let hangup_result: Result<(), String> = tokio::task::block_in_place(move || {
             tokio::runtime::Handle::current().block_on(async move {
                 let mut call = self.call_handle_lock.as_ref().unwrap().lock().await;
-                // full stop the queue
+                // full stop the queue, and forget anything still loading
+                self.pending.lock().unwrap().clear();
                 call.queue().stop();
                 if let Some(_) = call.current_connection() {
                     if let Err(_) = call.leave().await {
@@ -355,18 +429,7 @@ impl AudioPlayer {
     }
 
     async fn make_ytdl_track(&mut self, target: &str, search: bool) -> Result<Track, Error> {
-        //warn!("Loading url: {}", target);
-        // Create our player
-        let youtube_input = match search {
-            true => ytdl_search(target).await,
-            false => ytdl(target).await,
-        }?;
-        let metadata = youtube_input.metadata.clone();
-        warn!("Loaded up track: {} - {}", metadata.title.unwrap(), metadata.source_url.unwrap());
-        let (audio, _track_handle) = create_player(youtube_input);
-        // Give it the handle to end the call if need be
-        // Record our track object
-        return Ok(audio);
+        load_track(&self.settings, self.guild_id, target, search).await
     }
 
     async fn play_only_track(&mut self, track: Track) -> Result<(), String> {
@@ -414,7 +477,10 @@ impl AudioPlayer {
             return Err(String::from("Too many arguments given to driveby"));
         }
         match args.first().unwrap() {
-            Token::Generic(t) => self.process_driveby(ctx, new_message, t, false).await,
+            Token::Generic(t) => {
+                check_url(t)?;
+                self.process_driveby(ctx, new_message, t, false).await
+            }
             _ => return Err(String::from("Bug, not given a generic argument")),
         }
     }
@@ -458,7 +524,10 @@ impl AudioPlayer {
             return Err(String::from("Too many arguments given to play"));
         }
         match args.first().unwrap() {
-            Token::Generic(t) => self.process_play(ctx, new_message, t, false).await,
+            Token::Generic(t) => {
+                check_url(t)?;
+                self.process_play(ctx, new_message, t, false).await
+            }
             _ => return Err(String::from("Bug, not given a generic argument")),
         }
 
@@ -507,37 +576,28 @@ impl AudioPlayer {
             return Err(String::from("told to queue, but nothing given"));
         }
 
-        let mut tracks = Vec::<Track>::new();
+        let mut requests = Vec::<TrackRequest>::new();
         for url_to_play in args {
             match url_to_play {
                 Token::Generic(url) => {
+                    check_url(&url)?;
                     warn!("Told to queue {}", url);
-                    // Make the track
-                    match self.make_ytdl_track(url.as_str(), false).await {
-                        Ok(t) => {
-                            warn!("Successfully created track");
-                            tracks.push(t);
-                        }
-                        Err(e) => {
-                            return Err(String::from(format!("Couldn't create track: {}", e)));
-                        }
-                    }
+                    requests.push(TrackRequest::new(url, None));
                 }
                 _ => {
                     return Err(String::from("given invalid token to play"));
                 }
             }
         }
-        // If we get here it means we have a vec of successfully created tracks
-        //Join the call
+        //Join the call, no need to wait on the tracks for that
         self.join_summoner(&new_message, &ctx).await?;
         warn!("Joined summoner");
         // Make sure our idle action is set to timeout
         self.set_idle_check(TrackEndAction::TIMEOUT);
-        let mut call = lock_call_async!(self.call_handle_lock);
-        for track in tracks {
-            call.enqueue(track);
-            warn!("Queued track");
+        // Load them up in the background, playlists and all, they go into the call's queue as they're ready
+        self.pending.lock().unwrap().extend(requests.iter().cloned());
+        if let Err(e) = self.loader.as_ref().unwrap().send(requests) {
+            return Err(String::from(format!("Track loader isn't running: {}", e)));
         }
         Ok(())
     }
@@ -557,6 +617,7 @@ impl AudioPlayer {
             }
             false => {
                 if let Token::Generic(url_to_play) = args.first().unwrap() {
+                    check_url(url_to_play)?;
                     warn!("Told to queue next {}", url_to_play);
                     // Make the track
                     let track = self.make_ytdl_track(url_to_play, false).await;
@@ -603,23 +664,30 @@ impl AudioPlayer {
             }
         }
 
-        // validate that none of our removals are larger than our playlist
-        let playlist_len = {
-            let call = self.call_handle_lock.as_ref().unwrap().lock().await;
-            call.queue().len()
-        };
-        if playlist_len == 0 {
+        // validate that none of our removals are larger than our playlist, tracks still loading included.
+        // Hang on to the call so nothing finishes loading and shifts things around on us
+        let call = self.call_handle_lock.as_ref().unwrap().lock().await;
+        let mut pending = self.pending.lock().unwrap();
+        let playlist_len = call.queue().len();
+        if playlist_len + pending.len() == 0 {
             return Err(String::from("Empty playlist"));
         }
         for ind in &indices_to_rm {
             // If our index is out of range or 0, the currently playing track
-            if (*ind > playlist_len-1) || (*ind < 1 ) {
+            if (*ind > playlist_len + pending.len() - 1) || (*ind < 1 ) {
                 return Err(String::from(format!("Index {} is invalid", ind)));
             }
         }
 
+        // Anything past the call's queue is still loading, it just needs forgetting
+        let mut pending_idx = playlist_len;
+        pending.retain(|_| {
+            pending_idx += 1;
+            !indices_to_rm.contains(&(pending_idx - 1))
+        });
+        drop(pending);
+
         // Remove desired indices
-        let call = self.call_handle_lock.as_ref().unwrap().lock().await;
         call.queue().modify_queue(
             |q| {
                 let mut removalvec: Vec<Uuid> = Vec::new();
@@ -701,6 +769,130 @@ impl AudioPlayer {
         Ok(())
     }
 
+    /// Show the volume, or set it for what's playing, what's queued and everything after
+    pub async fn process_volume(&mut self, ctx: &Context, args: Vec<Token>) -> Result<(), String> {
+        let volume = match args.first() {
+            Some(Token::Generic(s)) => parse_percent(s)?,
+            Some(_) => return Err(String::from("Invalid token given")),
+            None => {
+                return self.say(ctx, format!("Volume is at {}%", self.settings.volume(self.guild_id))).await;
+            }
+        };
+        self.settings.set_volume(self.guild_id, volume);
+        let queue = {
+            let call = lock_call_async!(self.call_handle_lock);
+            call.queue().current_queue()
+        };
+        for track in queue {
+            let track_volume = self.settings.track_volume(self.guild_id, track.metadata().source_url.as_deref());
+            if let Err(e) = track.set_volume(track_volume) {
+                warn!("Couldn't change a queued track's volume: {}", e);
+            }
+        }
+        warn!("Set volume to {}%", volume);
+        self.say(ctx, format!("Volume set to {}%", volume)).await
+    }
+
+    /// Show or set how much louder or quieter the current track plays than everything else,
+    /// remembered for the next time it gets played
+    pub async fn process_gain(&mut self, ctx: &Context, args: Vec<Token>) -> Result<(), String> {
+        let current = {
+            let call = lock_call_async!(self.call_handle_lock);
+            call.queue().current()
+        };
+        let track = current.ok_or(String::from("Nothing's playing"))?;
+        let source_url = track.metadata().source_url.clone().ok_or(String::from("Current track has no url to remember it by"))?;
+        let gain = match args.first() {
+            Some(Token::Generic(s)) => parse_percent(s)?,
+            Some(_) => return Err(String::from("Invalid token given")),
+            None => {
+                return self.say(ctx, format!("Track gain is {}%", self.settings.gain(self.guild_id, &source_url))).await;
+            }
+        };
+        self.settings.set_gain(self.guild_id, &source_url, gain);
+        if let Err(e) = track.set_volume(self.settings.track_volume(self.guild_id, Some(&source_url))) {
+            return Err(String::from(format!("Error changing track volume: {}", e)));
+        }
+        warn!("Set gain for {} to {}%", source_url, gain);
+        self.say(ctx, format!("Track gain set to {}%", gain)).await
+    }
+
+    /// Jump around in the current track
+    pub async fn process_seek(&self, ctx: &Context, args: Vec<Token>, from: SeekFrom) -> Result<(), String> {
+        let amount = match args.first() {
+            Some(Token::Generic(s)) => parse_time(s)?,
+            _ => return Err(String::from("Invalid token given")),
+        };
+        let current = {
+            let call = lock_call_async!(self.call_handle_lock);
+            call.queue().current()
+        };
+        let track = current.ok_or(String::from("Nothing's playing"))?;
+        if !track.is_seekable() {
+            return Err(String::from("Can't seek in this track"));
+        }
+        let position = match track.get_info().await {
+            Ok(state) => state.position,
+            Err(e) => return Err(String::from(format!("Error getting track state: {}", e))),
+        };
+        let target = match from {
+            SeekFrom::Start => amount,
+            SeekFrom::Forward => position + amount,
+            SeekFrom::Back => position.saturating_sub(amount),
+        };
+        if let Some(total) = track.metadata().duration {
+            if target >= total {
+                return Err(String::from(format!("{} is past the end of the track ({})", format_time(target), format_time(total))));
+            }
+        }
+        if let Err(e) = track.seek_time(target) {
+            return Err(String::from(format!("Error seeking track: {}", e)));
+        }
+        warn!("Seeked to {:?}", target);
+        self.say(ctx, format!("Jumped to {}", format_time(target))).await
+    }
+
+    /// Say what's playing and how far into it we are
+    pub async fn process_now_playing(&self, ctx: &Context) -> Result<(), String> {
+        let current = {
+            let call = lock_call_async!(self.call_handle_lock);
+            call.queue().current()
+        };
+        let track = current.ok_or(String::from("Nothing's playing"))?;
+        let state = match track.get_info().await {
+            Ok(s) => s,
+            Err(e) => return Err(String::from(format!("Error getting track state: {}", e))),
+        };
+        let metadata = track.metadata();
+        let title = metadata.track.as_ref().or(metadata.title.as_ref()).cloned().unwrap_or(String::from("Unknown track"));
+        let icon = match state.playing {
+            PlayMode::Pause => "⏸️",
+            _ => "▶️",
+        };
+        let mut text = format!("{} **{}**", icon, title);
+        if let Some(artist) = &metadata.artist {
+            text.push_str(format!(", {}", artist).as_str());
+        }
+        match metadata.duration {
+            Some(total) => text.push_str(format!(
+                "\n`{}` {} `{}`",
+                format_time(state.position), progress_bar(state.position, total), format_time(total)
+            ).as_str()),
+            None => text.push_str(format!("\n`{}`", format_time(state.position)).as_str()),
+        }
+        if let Some(url) = &metadata.source_url {
+            text.push_str(format!("\n<{}>", url).as_str());
+        }
+        self.say(ctx, text).await
+    }
+
+    async fn say(&self, ctx: &Context, text: String) -> Result<(), String> {
+        match self.audio_text_channel.say(ctx.http.clone(), text).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(String::from(format!("Failed to send message: {}", e))),
+        }
+    }
+
     /// Remove all the tracks except the one currently playing
     pub fn clear_queue_locking(&self) -> Result<(), String> {
         let mut call = lock_call!(self.call_handle_lock);
@@ -708,7 +900,17 @@ impl AudioPlayer {
     }
     fn clear_queue(&self, call: &Call) -> Result<(), String> {
 
+        // Tracks still loading go too
+        let had_pending = {
+            let mut pending = self.pending.lock().unwrap();
+            let had_pending = !pending.is_empty();
+            pending.clear();
+            had_pending
+        };
         if call.queue().is_empty() {
+            if had_pending {
+                return Ok(());
+            }
             return Err(String::from("Queue is empty, can't clear shit"));
         }
 
@@ -748,9 +950,10 @@ impl AudioPlayer {
             })
         });
         let queue = call.queue().current_queue();
+        let pending: Vec<TrackRequest> = self.pending.lock().unwrap().iter().cloned().collect();
         let mut track_list = String::from("```\n");
 
-        match queue.is_empty() {
+        match queue.is_empty() && pending.is_empty() {
             true => {
                 return Err(String::from("Queue is empty"));
             }
@@ -776,10 +979,20 @@ impl AudioPlayer {
                         track_string.push_str(format!(", {}", x).as_str());
                     }
                     if let Some(x) = &metadata.duration {
-                        track_string.push_str(format!(", {:#?}\n", x).as_str());
+                        track_string.push_str(format!(", {}", format_time(*x)).as_str());
                     }
+                    track_string.push('\n');
                     track_list.push_str(track_string.as_str());
                 }
+                for (i, request) in pending.iter().enumerate() {
+                    let line = format!("{} - {} (loading)\n", queue.len() + i, request.name());
+                    // Long playlists can go past what discord will take
+                    if track_list.chars().count() + line.chars().count() > 1900 {
+                        track_list.push_str(format!("...and {} more loading\n", pending.len() - i).as_str());
+                        break;
+                    }
+                    track_list.push_str(line.as_str());
+                }
                 track_list.push_str("```");
                 let send_result = tokio::task::block_in_place(move || {
                     tokio::runtime::Handle::current().block_on(async move {
@@ -802,6 +1015,238 @@ impl AudioPlayer {
 }
 
 
+// Make a track out of a url or search, at the volume it should play at. Restartable so it can be
+// seeked, lazy so yt-dlp only starts streaming once it's played or prefetched
+async fn load_track(settings: &AudioSettings, guild_id: u64, target: &str, search: bool) -> Result<Track, Error> {
+    let youtube_input: Input = match search {
+        true => Restartable::ytdl_search(String::from(target), true).await,
+        false => Restartable::ytdl(String::from(target), true).await,
+    }?.into();
+    let metadata = youtube_input.metadata.clone();
+    let volume = settings.track_volume(guild_id, metadata.source_url.as_deref());
+    warn!("Loaded up track: {} - {}", metadata.title.unwrap_or_default(), metadata.source_url.unwrap_or_default());
+    let (mut audio, _track_handle) = create_player(youtube_input);
+    audio.set_volume(volume);
+    Ok(audio)
+}
+
+// What the loader needs to get tracks into the call
+struct Loader {
+    settings: AudioSettings,
+    guild_id: u64,
+    call_lock: Arc<Mutex<Call>>,
+    pending: Arc<std::sync::Mutex<VecDeque<TrackRequest>>>,
+    channel: ChannelId,
+    http: Arc<Http>,
+    // For starting the idle timeout once there's nothing left to load or play
+    idle_callback: TrackEndCallback,
+}
+
+// Load each batch of queued requests as it comes in, one batch after another so they go into the
+// call's queue in the order they were asked for
+async fn run_loader(mut batches: mpsc::UnboundedReceiver<Vec<TrackRequest>>, loader: Loader) {
+    while let Some(requests) = batches.recv().await {
+        let requests = loader.expand_playlists(requests).await;
+        loader.load_requests(requests).await;
+        // If every track failed or got removed, there's no track end coming to start the idle timeout
+        let done_loading = loader.pending.lock().unwrap().is_empty();
+        if done_loading && loader.call_lock.lock().await.queue().is_empty() {
+            warn!("Done loading with nothing to play");
+            let mut player = loader.idle_callback.audio_player.lock().await;
+            if let TrackEndAction::TIMEOUT = player.idle_callback_action {
+                loader.idle_callback.start_timeout(&mut player).await;
+            }
+        }
+    }
+    warn!("Track loader stopped");
+}
+
+impl Loader {
+    // Swap any playlists for the tracks in them, both here and in the pending list
+    async fn expand_playlists(&self, requests: Vec<TrackRequest>) -> Vec<TrackRequest> {
+        // Checking is one quick yt-dlp each, so do them all at once
+        let expanded = futures::future::join_all(
+            requests.iter().map(|r| playlist::expand(r.url.as_str(), MAX_PLAYLIST_ENTRIES))
+        ).await;
+        let mut expanded_requests = Vec::<TrackRequest>::new();
+        let mut summaries = Vec::<String>::new();
+        for (request, expansion) in requests.into_iter().zip(expanded) {
+            match expansion {
+                Ok(Some(list)) => {
+                    warn!("{} is a playlist with {} tracks", request.url, list.total);
+                    let entries: Vec<TrackRequest> = list.entries.iter()
+                        .map(|e| TrackRequest::new(e.url.clone(), e.title.clone()))
+                        .collect();
+                    // Only if nobody's removed or cleared it while we were checking
+                    let mut pending = self.pending.lock().unwrap();
+                    if let Some(i) = pending.iter().position(|r| r.id == request.id) {
+                        pending.remove(i);
+                        for (j, entry) in entries.iter().enumerate() {
+                            pending.insert(i + j, entry.clone());
+                        }
+                        summaries.push(list.summary());
+                        expanded_requests.extend(entries);
+                    }
+                }
+                Ok(None) => expanded_requests.push(request),
+                Err(e) => {
+                    warn!("Couldn't check {} for a playlist, queueing it as one track: {}", request.url, e);
+                    expanded_requests.push(request);
+                }
+            }
+        }
+        if !summaries.is_empty() {
+            if let Err(e) = self.channel.say(&self.http, summaries.join("\n")).await {
+                error!("Failed to send playlist summary: {}", e);
+            }
+        }
+        expanded_requests
+    }
+
+    // Load queued requests a few at a time, putting them into the call's queue in the order they were
+    // asked for. Anything that won't load gets reported at the end, the rest carry on without it
+    async fn load_requests(&self, requests: Vec<TrackRequest>) {
+        let mut loaded = stream::iter(requests.into_iter().map(|request| {
+            let settings = self.settings.clone();
+            let guild_id = self.guild_id;
+            async move {
+                let track = load_track(&settings, guild_id, request.url.as_str(), false).await;
+                (request, track)
+            }
+        })).buffered(LOAD_CONCURRENCY);
+
+        let mut failures = Vec::<String>::new();
+        while let Some((request, track)) = loaded.next().await {
+            let mut call = self.call_lock.lock().await;
+            // Only if nobody's removed or cleared it while we were loading
+            let still_wanted = {
+                let mut pending = self.pending.lock().unwrap();
+                match pending.iter().position(|r| r.id == request.id) {
+                    Some(i) => {
+                        pending.remove(i);
+                        true
+                    }
+                    None => false,
+                }
+            };
+            match track {
+                Ok(t) if still_wanted => {
+                    call.enqueue(t);
+                    warn!("Queued track {}", request.url);
+                    // It's up next, get it going
+                    if call.queue().len() == 2 {
+                        prefetch_next(call.queue());
+                    }
+                }
+                Ok(_) => warn!("{} was taken out of the queue while loading, dropping it", request.url),
+                Err(e) if still_wanted => {
+                    warn!("Couldn't create track {}: {}", request.url, e);
+                    failures.push(format!("Couldn't load {}: {}", request.name(), e));
+                }
+                Err(_) => {}
+            }
+        }
+
+        if !failures.is_empty() {
+            let mut text = String::new();
+            for (i, failure) in failures.iter().enumerate() {
+                if text.chars().count() + failure.chars().count() > 1900 {
+                    text.push_str(format!("...and {} more", failures.len() - i).as_str());
+                    break;
+                }
+                text.push_str(failure.as_str());
+                text.push('\n');
+            }
+            if let Err(e) = self.channel.say(&self.http, text).await {
+                error!("Failed to send track load failures: {}", e);
+            }
+        }
+    }
+}
+
+// Start streaming whatever's up after the current track, so there's no gap when it ends
+fn prefetch_next(queue: &TrackQueue) {
+    if let Some(next) = queue.current_queue().get(1) {
+        match next.make_playable() {
+            Ok(_) => warn!("Prefetching next track"),
+            Err(e) => warn!("Couldn't prefetch next track: {}", e),
+        }
+    }
+}
+
+// Only web links go to yt-dlp as they are, anything else could pass for one of its options
+fn check_url(arg: &str) -> Result<(), String> {
+    match reqwest::Url::parse(arg) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(()),
+        _ => Err(String::from(format!("{} isn't a link, use search to look something up", arg))),
+    }
+}
+
+// Read a volume like 80 or 80%
+fn parse_percent(arg: &str) -> Result<u32, String> {
+    let percent = match arg.trim_end_matches('%').parse::<u32>() {
+        Ok(p) => p,
+        Err(e) => return Err(String::from(format!("Couldn't parse percent from argument: {}", e))),
+    };
+    if percent > MAX_VOLUME {
+        return Err(String::from(format!("{}% is too loud, {}% is the max", percent, MAX_VOLUME)));
+    }
+    Ok(percent)
+}
+
+// Read a time like 1:23, 1:02:03, 90, 30s or 1m30s
+fn parse_time(arg: &str) -> Result<std::time::Duration, String> {
+    let bad = || String::from(format!("Couldn't read {} as a time, try 1:23 or 30s", arg));
+    let seconds = match arg.contains(':') {
+        true => {
+            let mut seconds = 0u64;
+            for part in arg.split(':') {
+                seconds = seconds * 60 + part.parse::<u64>().map_err(|_| bad())?;
+            }
+            seconds
+        }
+        false => {
+            let mut seconds = 0u64;
+            let mut number = String::new();
+            for c in arg.chars() {
+                match c {
+                    '0'..='9' => number.push(c),
+                    'h' | 'm' | 's' => {
+                        let n = number.parse::<u64>().map_err(|_| bad())?;
+                        seconds += n * match c { 'h' => 3600, 'm' => 60, _ => 1 };
+                        number.clear();
+                    }
+                    _ => return Err(bad()),
+                }
+            }
+            // A bare number is seconds
+            if !number.is_empty() {
+                seconds += number.parse::<u64>().map_err(|_| bad())?;
+            }
+            seconds
+        }
+    };
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// 1:23, or 1:02:03 once we're over an hour
+pub fn format_time(time: std::time::Duration) -> String {
+    let seconds = time.as_secs();
+    match seconds >= 3600 {
+        true => format!("{}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60),
+        false => format!("{}:{:02}", seconds / 60, seconds % 60),
+    }
+}
+
+fn progress_bar(elapsed: std::time::Duration, total: std::time::Duration) -> String {
+    let fraction = match total.as_secs_f64() > 0.0 {
+        true => (elapsed.as_secs_f64() / total.as_secs_f64()).min(1.0),
+        false => 0.0,
+    };
+    let marker = ((PROGRESS_BAR_WIDTH - 1) as f64 * fraction).round() as usize;
+    (0..PROGRESS_BAR_WIDTH).map(|i| if i == marker { '🔘' } else { '▬' }).collect()
+}
+
 // Very specific struct only for the purpose of leaving the call if nothing is playing after an idle timeout
 #[derive(Clone)]
 struct TrackEndCallback {
@@ -810,6 +1255,56 @@ struct TrackEndCallback {
 }
 
 
+impl TrackEndCallback {
+    // (Re)start the countdown to leaving the call if nothing's playing by the end of it
+    async fn start_timeout(&self, player: &mut AudioPlayer) {
+        // If we have an existing handle, abort it to start again
+        if let Some(timeout_handle) = player.timeout_handle.clone() {
+            let handle = timeout_handle.lock().await;
+            handle.abort();
+            warn!("Aborted existing handle");
+        }
+        // Spawn our thread to wait our timeout amount
+        // clone our stuff for use in task
+        let player_clone = self.audio_player.clone();
+        let timeout = self.timeout.clone();
+        player.timeout_handle = Some(Arc::new(Mutex::new(tokio::spawn(async move {
+            tokio::time::sleep(timeout).await; // We use tokio's sleep because it's abortable
+            warn!("Reached our timeout");
+            let mut player = player_clone.lock().await;
+            // Check to make sure we're not currently playing a song or our queue is empty
+            let queue = { // Do this in a closure so we drop the call lock when done
+                let call = player.call_handle_lock.as_ref().unwrap().lock().await;
+                call.queue().clone()
+            };
+            if !queue.is_empty() {
+                if let Some(h) = queue.current() {
+                    match h.get_info().await {
+                        Ok(s) => {
+                            if s.playing == PlayMode::Play {
+                                warn!("Still playing a track, not going to shutdown");
+                            }
+                        }
+                        Err(e) => {
+                            error!("Error getting track state, probably ended, shutting down: {}", e);
+                            player.shutdown().unwrap();
+                        }
+                    }
+                }
+            }
+            else if !player.pending.lock().unwrap().is_empty() {
+                // The loader starts us again once it's done
+                warn!("Still loading tracks, not going to shutdown");
+            }
+            else {
+                player.shutdown().unwrap();
+                warn!("Queue was empty, shutting down player");
+            }  
+        }))));
+        warn!("spawned tokio timeout task");
+    }
+}
+
 // Multi-use callback, installed in track end events and whatever other cases I want to write in
 #[async_trait]
 impl SongBirdEventHandler for TrackEndCallback {
@@ -822,46 +1317,7 @@ impl SongBirdEventHandler for TrackEndCallback {
                 match &player.idle_callback_action {
                     // Timeout the call after inactivity
                     TrackEndAction::TIMEOUT => {
-                        // If we have an existing handle, abort it to start again
-                        if let Some(timeout_handle) = player.timeout_handle.clone() {
-                            let handle = timeout_handle.lock().await;
-                            handle.abort();
-                            warn!("Aborted existing handle");
-                        }
-                        // Spawn our thread to wait our timeout amount
-                        // clone our stuff for use in task
-                        let player_clone = self.audio_player.clone();
-                        let timeout = self.timeout.clone();
-                        player.timeout_handle = Some(Arc::new(Mutex::new(tokio::spawn(async move {
-                            tokio::time::sleep(timeout).await; // We use tokio's sleep because it's abortable
-                            warn!("Reached our timeout");
-                            let mut player = player_clone.lock().await;
-                            // Check to make sure we're not currently playing a song or our queue is empty
-                            let queue = { // Do this in a closure so we drop the call lock when done
-                                let call = player.call_handle_lock.as_ref().unwrap().lock().await;
-                                call.queue().clone()
-                            };
-                            if !queue.is_empty() {
-                                if let Some(h) = queue.current() {
-                                    match h.get_info().await {
-                                        Ok(s) => {
-                                            if s.playing == PlayMode::Play {
-                                                warn!("Still playing a track, not going to shutdown");
-                                            }
-                                        }
-                                        Err(e) => {
-                                            error!("Error getting track state, probably ended, shutting down: {}", e);
-                                            player.shutdown().unwrap();
-                                        }
-                                    }
-                                }
-                            }
-                            else {
-                                player.shutdown().unwrap();
-                                warn!("Queue was empty, shutting down player");
-                            }  
-                        }))));
-                        warn!("spawned tokio timeout task");
+                        self.start_timeout(&mut player).await;
                     }
                     // Leave immediately
                     TrackEndAction::LEAVE => {
@@ -914,4 +1370,23 @@ impl SongBirdEventHandler for TrackEndCallback {
         
         return None;
     }
-}
\ No newline at end of file
+}
+
+
+// Gets the next track in the queue streaming as soon as the one before it starts playing
+#[derive(Clone)]
+struct PrefetchCallback {
+    call_lock: Arc<Mutex<Call>>,
+}
+
+#[async_trait]
+impl SongBirdEventHandler for PrefetchCallback {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        let queue = {
+            let call = self.call_lock.lock().await;
+            call.queue().clone()
+        };
+        prefetch_next(&queue);
+        None
+    }
+}
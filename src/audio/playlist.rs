@@ -0,0 +1,102 @@
+// Turning playlist urls into the tracks in them, with yt-dlp doing the hard part
+use serde::Deserialize;
+use tokio::process::Command;
+
+// What yt-dlp -J --flat-playlist gives us, only the bits we care about
+#[derive(Deserialize, Debug)]
+struct FlatPlaylist {
+    #[serde(rename = "_type")]
+    kind: Option<String>,
+    title: Option<String>,
+    #[serde(default)]
+    entries: Vec<FlatEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FlatEntry {
+    id: Option<String>,
+    url: Option<String>,
+    title: Option<String>,
+    duration: Option<f64>,
+    channel: Option<String>,
+    uploader: Option<String>,
+}
+
+impl FlatEntry {
+    // Older yt-dlps hand back bare youtube ids instead of urls
+    fn full_url(&self) -> Option<String> {
+        match (&self.url, &self.id) {
+            (Some(url), _) if url.starts_with("http") => Some(url.clone()),
+            (_, Some(id)) => Some(format!("https://www.youtube.com/watch?v={}", id)),
+            _ => None,
+        }
+    }
+}
+
+/// A track in a playlist, not loaded yet
+#[derive(Debug, Clone)]
+pub struct PlaylistEntry {
+    pub url: String,
+    pub title: Option<String>,
+    pub duration: Option<std::time::Duration>,
+    // Who put it up
+    pub channel: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    pub title: Option<String>,
+    pub entries: Vec<PlaylistEntry>,
+    // How many entries there were before we cut it down to size
+    pub total: usize,
+}
+
+impl Playlist {
+    /// What to tell everyone when the playlist gets queued
+    pub fn summary(&self) -> String {
+        let mut text = format!(
+            "Queueing {} tracks from **{}**",
+            self.entries.len(), self.title.as_deref().unwrap_or("a playlist")
+        );
+        if self.total > self.entries.len() {
+            text.push_str(format!(", left off {} more over the limit", self.total - self.entries.len()).as_str());
+        }
+        text
+    }
+}
+
+/// Ask yt-dlp what's in a url, giving back None when it's just a single track.
+/// Only the first `cap` entries are kept
+pub async fn expand(url: &str, cap: usize) -> Result<Option<Playlist>, String> {
+    let output = Command::new("yt-dlp")
+        // Everything after the -- is the url, so nobody can sneak their own options in
+        .args(&["--flat-playlist", "-J", "--no-warnings", "--", url])
+        .output().await
+        .map_err(|e| format!("Couldn't run yt-dlp: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("yt-dlp failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let flat: FlatPlaylist = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Couldn't read yt-dlp's output: {}", e))?;
+    if flat.kind.as_deref() != Some("playlist") {
+        return Ok(None);
+    }
+    let total = flat.entries.len();
+    let entries = flat.entries.into_iter()
+        .filter_map(|e| {
+            let url = e.full_url()?;
+            Some(PlaylistEntry {
+                url: url,
+                title: e.title,
+                duration: e.duration.map(std::time::Duration::from_secs_f64),
+                channel: e.channel.or(e.uploader),
+            })
+        })
+        .take(cap)
+        .collect();
+    Ok(Some(Playlist {
+        title: flat.title,
+        entries: entries,
+        total: total,
+    }))
+}
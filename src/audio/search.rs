@@ -0,0 +1,88 @@
+// Letting people choose from youtube's search results, instead of playing whatever comes up first
+use std::time::Duration;
+
+use serenity::model::channel::{Message, ReactionType};
+use serenity::prelude::Context;
+use tokio::select;
+
+use crate::audio::player::format_time;
+use crate::audio::playlist::{self, PlaylistEntry};
+
+// How many results to choose from
+const SEARCH_RESULTS: usize = 5;
+// How long whoever searched gets to pick
+const PICK_TIMEOUT: Duration = Duration::from_secs(30);
+const NUMBER_EMOJI: &[&str] = &["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣"];
+
+/// The top youtube results for a search, without loading any of them
+pub async fn search(query: &str) -> Result<Vec<PlaylistEntry>, String> {
+    // yt-dlp treats searches like a playlist of the results
+    let results = playlist::expand(&format!("ytsearch{}:{}", SEARCH_RESULTS, query), SEARCH_RESULTS).await?;
+    Ok(results.map(|r| r.entries).unwrap_or_default())
+}
+
+// Title, channel and length, whatever we know of them
+fn describe(entry: &PlaylistEntry) -> String {
+    let mut text = format!("**{}**", entry.title.as_deref().unwrap_or(entry.url.as_str()));
+    if let Some(channel) = &entry.channel {
+        text.push_str(format!(" - {}", channel).as_str());
+    }
+    if let Some(duration) = entry.duration {
+        text.push_str(format!(" ({})", format_time(duration)).as_str());
+    }
+    text
+}
+
+/// List the results for a search and wait for whoever searched to pick one, either by replying
+/// with its number or reacting with it. Gives back the url they picked, or None if they didn't
+pub async fn pick(ctx: &Context, msg: &Message, query: &str) -> Result<Option<String>, String> {
+    let results = search(query).await?;
+    if results.is_empty() {
+        return Err(format!("No results for {}", query));
+    }
+    let mut text = format!("Results for **{}**, pick one within {}s:", query, PICK_TIMEOUT.as_secs());
+    for (emoji, entry) in NUMBER_EMOJI.iter().zip(results.iter()) {
+        text.push_str(format!("\n{} {}", emoji, describe(entry)).as_str());
+    }
+    let listing = match msg.channel_id.say(&ctx.http, text).await {
+        Ok(m) => m,
+        Err(e) => return Err(format!("Failed to send search results: {}", e)),
+    };
+    for emoji in NUMBER_EMOJI.iter().take(results.len()) {
+        if let Err(e) = listing.react(&ctx.http, ReactionType::Unicode(String::from(*emoji))).await {
+            warn!("Couldn't add pick reaction: {}", e);
+        }
+    }
+
+    let count = results.len();
+    let reply = msg.channel_id.await_reply(ctx)
+        .author_id(msg.author.id)
+        .filter(move |m| matches!(m.content.trim().parse::<usize>(), Ok(n) if n >= 1 && n <= count))
+        .timeout(PICK_TIMEOUT);
+    let reaction = listing.await_reaction(ctx)
+        .author_id(msg.author.id)
+        .filter(move |r| matches!(&r.emoji, ReactionType::Unicode(e) if NUMBER_EMOJI.iter().take(count).any(|n| n == e)))
+        .timeout(PICK_TIMEOUT);
+    // Whichever way they pick first
+    let picked = select! {
+        Some(reply) = reply => reply.content.trim().parse::<usize>().ok(),
+        Some(reaction) = reaction => match &reaction.as_inner_ref().emoji {
+            ReactionType::Unicode(e) => NUMBER_EMOJI.iter().position(|n| n == e).map(|i| i + 1),
+            _ => None,
+        },
+        else => None,
+    };
+
+    match picked.and_then(|n| results.get(n - 1)) {
+        Some(entry) => {
+            warn!("Picked search result {}", entry.url);
+            Ok(Some(entry.url.clone()))
+        }
+        None => {
+            if let Err(e) = msg.channel_id.say(&ctx.http, "Nothing picked, never mind").await {
+                warn!("Failed to send pick timeout: {}", e);
+            }
+            Ok(None)
+        }
+    }
+}
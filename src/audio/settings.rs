@@ -0,0 +1,105 @@
+// Audio settings that should outlive a restart, like how loud each guild likes it
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::persist::write_atomic;
+
+/// Percent volume a guild starts out at
+pub const DEFAULT_VOLUME: u32 = 100;
+/// Nobody needs it louder than this
+pub const MAX_VOLUME: u32 = 200;
+
+fn default_volume() -> u32 {
+    DEFAULT_VOLUME
+}
+
+/// What we remember for a guild
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GuildAudio {
+    #[serde(default = "default_volume")]
+    pub volume: u32,
+    // Percent adjustments for tracks that are too loud or quiet on their own, by source url
+    #[serde(default)]
+    pub track_gain: HashMap<String, u32>,
+}
+
+impl Default for GuildAudio {
+    fn default() -> Self {
+        GuildAudio {
+            volume: DEFAULT_VOLUME,
+            track_gain: HashMap::new(),
+        }
+    }
+}
+
+/// Everyone's audio settings, saved to a json file whenever they change.
+/// Clones share the same settings
+#[derive(Clone)]
+pub struct AudioSettings {
+    path: PathBuf,
+    guilds: Arc<Mutex<HashMap<u64, GuildAudio>>>,
+}
+
+impl AudioSettings {
+    pub fn open(path: PathBuf) -> AudioSettings {
+        let guilds = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str::<HashMap<u64, GuildAudio>>(&contents) {
+                Ok(g) => g,
+                Err(e) => {
+                    error!("Couldn't parse audio settings {}, using defaults: {}", path.display(), e);
+                    HashMap::new()
+                }
+            },
+            Err(_) => HashMap::new(),
+        };
+        AudioSettings {
+            path: path,
+            guilds: Arc::new(Mutex::new(guilds)),
+        }
+    }
+
+    pub fn volume(&self, guild: u64) -> u32 {
+        self.guilds.lock().unwrap().get(&guild).map(|g| g.volume).unwrap_or(DEFAULT_VOLUME)
+    }
+
+    pub fn set_volume(&self, guild: u64, volume: u32) {
+        let mut guilds = self.guilds.lock().unwrap();
+        guilds.entry(guild).or_default().volume = volume;
+        self.save(&guilds);
+    }
+
+    pub fn gain(&self, guild: u64, source_url: &str) -> u32 {
+        self.guilds.lock().unwrap().get(&guild)
+            .and_then(|g| g.track_gain.get(source_url).copied())
+            .unwrap_or(100)
+    }
+
+    /// Set a track's gain, 100 being no adjustment at all
+    pub fn set_gain(&self, guild: u64, source_url: &str, gain: u32) {
+        let mut guilds = self.guilds.lock().unwrap();
+        let track_gain = &mut guilds.entry(guild).or_default().track_gain;
+        match gain {
+            100 => track_gain.remove(source_url),
+            _ => track_gain.insert(String::from(source_url), gain),
+        };
+        self.save(&guilds);
+    }
+
+    /// How loud a track should play in a guild, as songbird wants it (1.0 is unchanged)
+    pub fn track_volume(&self, guild: u64, source_url: Option<&str>) -> f32 {
+        let gain = source_url.map(|u| self.gain(guild, u)).unwrap_or(100);
+        (self.volume(guild) as f32 / 100.0) * (gain as f32 / 100.0)
+    }
+
+    fn save(&self, guilds: &HashMap<u64, GuildAudio>) {
+        let written = serde_json::to_string_pretty(guilds).map_err(|e| e.to_string())
+            .and_then(|contents| write_atomic(&self.path, contents));
+        if let Err(e) = written {
+            error!("Failed to save audio settings {}: {}", self.path.display(), e);
+        }
+    }
+}
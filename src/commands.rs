@@ -1,8 +1,15 @@
 use logos::{Logos, Span};
 
 use std::sync::Arc;
+use std::collections::HashSet;
 use tokio::sync::Mutex;
-use crate::audio::player::AudioPlayer;
+use crate::audio::player::{AudioPlayer, SeekFrom};
+use crate::audio::search;
+use crate::archive::Archive;
+use crate::reddit::SnifferPost;
+use crate::stats;
+
+use chrono::{NaiveDate, TimeZone, Utc};
 
 use serenity::model::channel::Message;
 use serenity::prelude::Context;
@@ -40,6 +47,26 @@ pub enum Token {
     Rm,
     #[token("goto")]
     Goto,
+    #[token("volume")]
+    Volume,
+    #[token("gain")]
+    Gain,
+    #[token("seek")]
+    Seek,
+    #[token("forward")]
+    Forward,
+    #[token("back")]
+    Back,
+    #[token("np")]
+    Np,
+    #[token("archive")]
+    Archive,
+    #[token("user")]
+    User,
+    #[token("stats")]
+    Stats,
+    #[token("digest")]
+    Digest,
 
     #[regex("[\\S]+", |lex| String::from(lex.slice()))] // regex match any non whitespace
     Generic(String),
@@ -78,24 +105,173 @@ impl AudioCommands {
         &[Token::Next, Token::Arguments],
         &[Token::Goto, Token::Argument],
         &[Token::Rm, Token::Arguments],
+        &[Token::Volume],
+        &[Token::Volume, Token::Argument],
+        &[Token::Gain],
+        &[Token::Gain, Token::Argument],
+        &[Token::Seek, Token::Argument],
+        &[Token::Forward, Token::Argument],
+        &[Token::Back, Token::Argument],
+        &[Token::Np],
+    ];
+}
+
+// And this one digs through the sniffer archive
+struct ArchiveCommands;
+impl ArchiveCommands {
+    const EXPECTED_TOKENS: &'static [&'static [Token]] = &[
+        &[Token::Archive, Token::Search, Token::Arguments],
+        &[Token::Archive, Token::User, Token::Arguments],
+        &[Token::Stats, Token::Argument],
+        &[Token::Digest],
     ];
 }
 
+// Don't flood the channel with search results
+const MAX_ARCHIVE_RESULTS: usize = 10;
+
 fn get_tokens(string: &String) -> Vec<(Token, Span)> {
     return Token::lexer(string).spanned().collect(); // Drop the span, we don't care about it
 }
 
 
-pub fn tokenize(string: &String) -> Result<(Vec<Token>, Option<Vec<Token>>), String> {
-    let tokenized = get_tokens(string);
-    let tokens: Vec<Token> = tokenized.iter().map(|x| x.0.clone()).collect(); // Collect all the Tokens into a vector, drop the span
-    // Big yucky, but it goes through tokens and keeps everything that's a generic into a new vec
-    let args = tokens.iter().cloned().filter(|x| { if let Token::Generic(_) = x { return true } false } ).collect();
-    //warn!("These are the args: {:?}", args);
+// How a message's tokens lined up against one of our known commands
+enum ChainMatch {
+    // The command's own tokens, and its arguments
+    Matched(Vec<Token>, Vec<Token>),
+    // It matched, but there was more on the end than the command takes
+    Leftovers,
+    NoMatch,
+}
+
+// Check parsed tokens against a single known command. When we're being loose, keywords can stand in
+// as arguments by their text, so searching for "back in black" isn't three commands
+fn match_chain(tokens: &[(Token, Span)], content: &str, token_array: &[Token], loose: bool) -> ChainMatch {
+    let as_argument = |(token, span): &(Token, Span)| match token {
+        Token::Generic(_) => Some(token.clone()),
+        Token::Error => None,
+        _ if loose => Some(Token::Generic(String::from(&content[span.clone()]))),
+        _ => None,
+    };
+    let mut command_tokens = Vec::<Token>::new();
+    let mut args = Vec::<Token>::new();
+    let mut parsed_tokens_iter = tokens.iter().peekable();
+    for token in token_array { // Loop through each token in array
+        trace!("Working on {:?}", token);
+        // If we've gotten to Arguments, everything that's left has to be an argument
+        if *token == Token::Arguments {
+            trace!("Processing infinite argument token");
+            if parsed_tokens_iter.peek().is_none() {
+                trace!("No tokens to process, expecting at least more than 0");
+                return ChainMatch::NoMatch;
+            }
+            for parsed in parsed_tokens_iter.by_ref() {
+                match as_argument(parsed) {
+                    Some(arg) => args.push(arg),
+                    None => {
+                        trace!("Didn't get an argument when we expected, found {:?}", parsed.0);
+                        return ChainMatch::NoMatch;
+                    }
+                }
+            }
+            break;
+        }
+        // Make sure we have another token in our parsed list
+        let parsed = match parsed_tokens_iter.next() {
+            Some(p) => p,
+            None => {
+                trace!("Ran out of parsed tokens, can't match {:?}", token_array);
+                return ChainMatch::NoMatch;
+            }
+        };
+        trace!("comparing expected: {:?} -- against : {:?}", token, parsed.0);
+        if *token == Token::Argument {
+            match as_argument(parsed) {
+                Some(arg) => args.push(arg),
+                None => return ChainMatch::NoMatch,
+            }
+        }
+        else if parsed.0 == *token {
+            command_tokens.push(token.clone());
+        }
+        else {
+            trace!("{:?} and {:?} didn't match", parsed.0, *token);
+            return ChainMatch::NoMatch;
+        }
+    }
+    // Make sure there's nothing left, making it a bad command with extra args
+    if parsed_tokens_iter.peek().is_some() {
+        return ChainMatch::Leftovers;
+    }
+    ChainMatch::Matched(command_tokens, args)
+}
+
+// Find the known command a message is, giving back its tokens and arguments
+fn match_command(content: &String) -> Result<(Vec<Token>, Option<Vec<Token>>), String> {
+
+    let tokens = get_tokens(content);
     if tokens.is_empty() {
         return Err(String::from("No tokens parsed in string"));
     }
-    return Ok((tokens, Some(args)));
+    trace!("Tokens: {:?}", tokens);
+
+    let mut leftovers = false;
+    // Keywords only get read as arguments when nothing matches with them as keywords
+    for loose in [false, true].iter() {
+        let known_commands = AudioCommands::EXPECTED_TOKENS.iter().chain(ArchiveCommands::EXPECTED_TOKENS.iter());
+        for token_array in known_commands { // Loop through our 2d array of known good token chains
+            trace!("Currently checking out token string for {:?}", token_array);
+            match match_chain(&tokens, content, token_array, *loose) {
+                ChainMatch::Matched(command_tokens, args) => {
+                    trace!("Args: {:?}", args);
+                    return Ok((command_tokens, Some(args)));
+                }
+                ChainMatch::Leftovers => leftovers = true,
+                ChainMatch::NoMatch => {}
+            }
+        }
+    }
+    if leftovers {
+        return Err(String::from("Matched a valid command, but we still have parsed tokens, making it bad"));
+    }
+    Err(String::from("No valid token chain has been found"))
+}
+
+// Pull the strings back out of our generic tokens
+fn generic_tokens_to_strings(tokens: Vec<Token>) -> Result<Vec<String>, String> {
+    tokens.into_iter().map(|token| match token {
+        Token::Generic(t) => Ok(t),
+        _ => Err(String::from("Bug, generic doesn't contain a string")),
+    }).collect()
+}
+
+// Turn a YYYY-MM-DD date into a unix timestamp, either the start or end of that day
+fn parse_date(date: &str, end_of_day: bool) -> Result<u64, String> {
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| format!("Couldn't read date {} (expected YYYY-MM-DD): {}", date, e))?;
+    let time = match end_of_day {
+        true => day.and_hms_opt(23, 59, 59),
+        false => day.and_hms_opt(0, 0, 0),
+    };
+    time.map(|t| Utc.from_utc_datetime(&t).timestamp().max(0) as u64).ok_or(format!("Bad date {}", date))
+}
+
+// A line per post, stopping before we'd go over discord's message limit
+fn format_archive_results(header: String, posts: &[SnifferPost]) -> String {
+    let mut text = header;
+    for post in posts.iter().take(MAX_ARCHIVE_RESULTS) {
+        let date = Utc.timestamp_opt(post.timestamp as i64, 0).single()
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        let title: String = post.title.chars().take(100).collect();
+        let link = post.permalink.clone().or(post.url.clone()).unwrap_or_default();
+        let line = format!("\n`{}` **{}** /r/{} by /u/{}\n<{}>", date, title, post.subreddit, post.author, link);
+        if text.chars().count() + line.chars().count() > 1900 {
+            break;
+        }
+        text.push_str(&line);
+    }
+    text
 }
 
 pub fn generic_tokens_to_string(tokens: Vec<Token>) -> Result<String, String> {
@@ -112,101 +288,30 @@ pub fn generic_tokens_to_string(tokens: Vec<Token>) -> Result<String, String> {
 #[derive(Clone)]
 pub struct Parser {
     audio_player: Arc<Mutex<AudioPlayer>>,
+    archive: Archive,
+    // Users picking from search results right now
+    pickers: Arc<std::sync::Mutex<HashSet<u64>>>,
 }
 impl Parser {
-    pub fn new(player_arc: Arc<Mutex<AudioPlayer>>) -> Parser {
+    pub fn new(player_arc: Arc<Mutex<AudioPlayer>>, archive: Archive) -> Parser {
         return Parser {
             audio_player: player_arc,
+            archive: archive,
+            pickers: Arc::new(std::sync::Mutex::new(HashSet::new())),
         }
     }
 
     // Our token matching function
     fn match_tokens(&self, msg: &Message) -> Result<(Vec<Token>, Option<Vec<Token>>), String> {
-        
-        let (tokens, generic_args) = tokenize(&msg.content)?;
-        // Create another array of tokens minus to use for our match table
-        let mut command_tokens = tokens.clone();
-        // We have to do the dumbness below because the if let is the only way to match generic enums
-        command_tokens.retain(|t| (
-            if let Token::Generic(_) = t {
-                false
-            }
-            else {
-                true
-            }
-
-        ));
-
-        trace!("Tokens: {:?}", tokens);
-        trace!("Args: {:?}", generic_args);
-        
-        'outer: for token_array in AudioCommands::EXPECTED_TOKENS { // Loop through our 2d array of known good token chains
-            let mut parsed_tokens_iter = tokens.clone().into_iter().peekable();
-            //let currently_checking_token = token_array[0].clone();
-            trace!("Currently checking out token string for {:?}", token_array);
-            for token in *token_array { // Loop through each token in array
-                trace!("Working on {:?}", token);
-                // If we've gotten to Arguments, break out to process N amount of arguments
-                // Verify the rest of the parsed tokens iterator and break out of the loop
-                if *token == Token::Arguments { 
-                    trace!("Processing infinite argument token");
-                    if parsed_tokens_iter.peek().is_none(){ 
-                        trace!("No tokens to process, expecting at least more than 0");
-                        // check other commands
-                        continue 'outer;
-                    }
-                    while let Some(t) = parsed_tokens_iter.next() { // While there's something in the iterator
-                        if let Token::Generic(_) = t {
-                            trace!("Got a generic where we expected it");
-                        }
-                        else {
-                            trace!("Didn't get a generic when we expected, found {:?}", t);
-                            continue 'outer;
-                        }
-                    }
-                    // if we get here they were all valid, break out of the checking loop
-                    break;
-                }
-                // Make sure we have another token in our parsed list
-                match parsed_tokens_iter.next() {
-                    Some(parsed_token) => {
-                        trace!("comparing expected: {:?} -- against : {:?}", token, parsed_token);
-                        // Check if our token is a generic first
-                        if let Token::Generic(_) = parsed_token {
-                            if *token != Token::Argument {
-                                // This means we don't have a valid match, as a generic is counted as a single argument
-                                trace!("{:?} isn't a generic, continuing", parsed_token);
-                                continue 'outer;
-                            }
-                        }
-                        // If not a generic match specific token
-                        else if parsed_token != *token {
-                            trace!("{:?} and {:?} didn't match, continuing", parsed_token, *token);
-                            continue 'outer;
-                        }
-                        // Otherwise we've matched a token, and we keep going
-                    }
-                    None => {
-                        //warn!("Ran out of parsed tokens, can't match {:?}, continuing", currently_checking_token);
-                        trace!("Ran out of parsed tokens, can't match {:?}, continuing", token_array);
-                        continue 'outer;
-                    },
-                    
-                }
-            }
-            // if we reach here, we've successfully matched a whole token chain
-            // Make sure there's nothing left, making it a bad command with extra args
-            if parsed_tokens_iter.peek().is_some(){ 
-                return Err(String::from("Matched a valid command, but we still have parsed tokens, making it bad"));
-            }
-            //return Ok((currently_checking_token, generic_args));     
-            return Ok((command_tokens, generic_args));     
-        }
-        Err(String::from("No valid token chain has been found"))
+        match_command(&msg.content)
     }
 
     // Our function matching table
     pub async fn process(&self, ctx: &Context, msg: &Message) -> Result<(), String> {
+        // A number from someone picking a search result is their pick, not a command
+        if self.pickers.lock().unwrap().contains(&msg.author.id.0) && msg.content.trim().parse::<usize>().is_ok() {
+            return Ok(());
+        }
         let (matched, args) = self.match_tokens(msg)?;
         //warn!("Matched {:?} with args {:?}", matched, args);
         match &matched[..] { // vec to slice (array) for nice matching
@@ -252,7 +357,19 @@ impl Parser {
                 locked_player.process_play_url(&ctx, &msg, args.unwrap()).await?;
 
             },
-            [Token::Play, Token::Search] | [Token::Search] => {
+            [Token::Search] => {
+                let search_string = generic_tokens_to_string(args.unwrap())?;
+                // Don't hold on to the player while we wait for them to pick
+                self.pickers.lock().unwrap().insert(msg.author.id.0);
+                let picked = search::pick(ctx, msg, search_string.trim()).await;
+                self.pickers.lock().unwrap().remove(&msg.author.id.0);
+                if let Some(url) = picked? {
+                    let mut locked_player = self.audio_player.lock().await;
+                    locked_player.process_play_url(&ctx, &msg, vec![Token::Generic(url)]).await?;
+                    locked_player.print_queue(ctx)?;
+                }
+            },
+            [Token::Play, Token::Search] => {
                 let mut locked_player = self.audio_player.lock().await;
                 let search_string = generic_tokens_to_string(args.unwrap()).unwrap();
                 //locked_player.process_play_search(&ctx, &msg, args.unwrap()).await?;
@@ -289,6 +406,61 @@ impl Parser {
                 locked_player.process_rm(args.unwrap()).await?;
 
             },
+            [Token::Volume] => {
+                let mut locked_player = self.audio_player.lock().await;
+                locked_player.process_volume(&ctx, args.unwrap()).await?;
+            },
+            [Token::Gain] => {
+                let mut locked_player = self.audio_player.lock().await;
+                locked_player.process_gain(&ctx, args.unwrap()).await?;
+            },
+            [Token::Seek] => {
+                let locked_player = self.audio_player.lock().await;
+                locked_player.process_seek(&ctx, args.unwrap(), SeekFrom::Start).await?;
+            },
+            [Token::Forward] => {
+                let locked_player = self.audio_player.lock().await;
+                locked_player.process_seek(&ctx, args.unwrap(), SeekFrom::Forward).await?;
+            },
+            [Token::Back] => {
+                let locked_player = self.audio_player.lock().await;
+                locked_player.process_seek(&ctx, args.unwrap(), SeekFrom::Back).await?;
+            },
+            [Token::Np] => {
+                let locked_player = self.audio_player.lock().await;
+                locked_player.process_now_playing(&ctx).await?;
+            },
+            [Token::Archive, Token::Search] => {
+                let terms = generic_tokens_to_strings(args.unwrap())?;
+                let found = self.archive.search(&terms);
+                let header = format!("Found {} archived posts matching \"{}\"", found.len(), terms.join(" "));
+                self.reply(ctx, msg, format_archive_results(header, &found)).await?;
+            },
+            [Token::Archive, Token::User] => {
+                let args = generic_tokens_to_strings(args.unwrap())?;
+                // archive user <name> [from] [to]
+                let (user, from, to) = match &args[..] {
+                    [user] => (user, 0, u64::MAX),
+                    [user, from] => (user, parse_date(from, false)?, Utc::now().timestamp() as u64),
+                    [user, from, to] => (user, parse_date(from, false)?, parse_date(to, true)?),
+                    _ => return Err(String::from("Usage: archive user <name> [from YYYY-MM-DD] [to YYYY-MM-DD]")),
+                };
+                let user = user.trim_start_matches("/u/").trim_start_matches("u/");
+                let found = self.archive.by_user(user, from, to);
+                let header = format!("Found {} archived posts by /u/{}", found.len(), user);
+                self.reply(ctx, msg, format_archive_results(header, &found)).await?;
+            },
+            [Token::Stats] => {
+                let id = generic_tokens_to_string(args.unwrap())?;
+                match self.archive.get(id.trim()) {
+                    Some(post) => self.reply(ctx, msg, stats::post_report(&post)).await?,
+                    None => return Err(format!("No archived post matching {}", id.trim())),
+                }
+            },
+            [Token::Digest] => {
+                let since = (Utc::now().timestamp() as u64).saturating_sub(stats::WEEK);
+                self.reply(ctx, msg, stats::digest(self.archive.since(since), since)).await?;
+            },
             _ => {
                 return Err(String::from(format!("Found a valid token that isn't in the table. You probably forgot to add parsing logic: {:?}", matched)));
             }
@@ -296,6 +468,13 @@ impl Parser {
         Ok(())
     }
 
+    async fn reply(&self, ctx: &Context, msg: &Message, text: String) -> Result<(), String> {
+        match msg.channel_id.say(&ctx.http, text).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Failed to send reply: {}", e)),
+        }
+    }
+
 }
 
 pub static HELP_TEXT: &str =
@@ -305,13 +484,13 @@ play \"url\"\n\
 play search \"song name\"\n\
 \t-searches youtube and plays what you enter\n\
 search \"song name\"\n\
-\t-the same thing as play search\n\
+\t-lists the top youtube results, pick one to play by replying with its number or reacting\n\
 driveby \"url\"\n\
 \t-driveby a channel with the given url\n\
 driveby search \"song name\"\n\
 \t-same as play search, but driveby\n\
 queue \"url\" *\n\
-\t-queue up as many urls as you type (separated by space) starts playing if queue is empty\n\
+\t-queue up as many urls as you type (separated by space) starts playing if queue is empty, playlists get all their tracks queued\n\
 next \"url\"\n\
 \t-queue up the given url to play next\n\
 goto X\n\
@@ -320,6 +499,14 @@ rm X Y etc\n\
 \t-remove queue elements, provide indices separated by spaces\n\
 list\n\
 \t-lists the current queue\n\
+np\n\
+\t-what's playing, and how far in we are\n\
+seek X\n\
+\t-jump to a time in the current track, like 1:23\n\
+forward X\n\
+\t-skip ahead in the current track, like 30s\n\
+back X\n\
+\t-go back in the current track, like 10s\n\
 pause\n\
 \t-pause currently playing track\n\
 resume\n\
@@ -328,10 +515,22 @@ skip\n\
 \t-skip the current track\n\
 clear\n\
 \t-clears everything in the queue but the song playing \n\
+volume [X]\n\
+\t-show or set the volume in percent (up to 200), sticks around for later tracks\n\
+gain [X]\n\
+\t-show or set how loud the current track is compared to the rest, remembered for next time\n\
 stop\n\
 \t-stop the player, but don't leave\n\
 leave\n\
 \t-tells the player to fuck outta here\n\
+archive search \"terms\"\n\
+\t-search every post the sniffer has seen\n\
+archive user \"name\" [from] [to]\n\
+\t-list a user's sniffed posts, dates are YYYY-MM-DD\n\
+stats \"post id or link\"\n\
+\t-show how a sniffed post is doing\n\
+digest\n\
+\t-the top sniffed posts from the last week\n\
 help\n\
 \t-show this\n\
 ```\
@@ -340,4 +539,38 @@ help\n\
 
 trait Process {
     fn process(&self) -> Result<(), String>;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(content: &str) -> Vec<String> {
+        let (_, args) = match_command(&String::from(content)).unwrap();
+        generic_tokens_to_strings(args.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn keywords_work_as_arguments() {
+        let (matched, _) = match_command(&String::from("search back in black")).unwrap();
+        assert_eq!(matched, vec![Token::Search]);
+        assert_eq!(args("search back in black"), vec!["back", "in", "black"]);
+        assert_eq!(args("archive search user stats"), vec!["user", "stats"]);
+        assert_eq!(args("play search forward motion"), vec!["forward", "motion"]);
+    }
+
+    #[test]
+    fn keywords_still_win_when_they_fit() {
+        let (matched, _) = match_command(&String::from("play search np")).unwrap();
+        assert_eq!(matched, vec![Token::Play, Token::Search]);
+        let (matched, _) = match_command(&String::from("archive user someone")).unwrap();
+        assert_eq!(matched, vec![Token::Archive, Token::User]);
+        assert_eq!(args("volume 50"), vec!["50"]);
+    }
+
+    #[test]
+    fn extra_arguments_are_an_error() {
+        assert!(match_command(&String::from("skip now")).is_err());
+        assert!(match_command(&String::from("goto 1 2")).is_err());
+    }
+}
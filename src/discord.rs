@@ -1,10 +1,15 @@
 // For sniffer post struct
-use crate::reddit::SnifferPost;
+use crate::reddit::{Announcement, PostChange, ChangeKind, SnifferPost, MessageRef, Posted, ChannelProgress};
 use crate::Secrets;
 use crate::audio::player::{AudioPlayer};
+use crate::audio::settings::AudioSettings;
 use crate::commands::Parser;
+use crate::archive::Archive;
 
 use std::sync::Arc;
+use std::borrow::Cow;
+use serde::Deserialize;
+use chrono::{TimeZone, Utc};
 use tokio::select;
 use tokio::sync::{RwLock, Mutex};
 use tokio_util::sync::CancellationToken;
@@ -12,10 +17,12 @@ use tokio_util::sync::CancellationToken;
 // For Discord
 use serenity::{
     prelude::*,
-    model::{id::{ChannelId, EmojiId}},
+    model::{id::{ChannelId, EmojiId, GuildId, MessageId}},
     model::{event::ResumedEvent, gateway::{Ready, Activity}},
     client::{Client, bridge::gateway::ShardManager},
-    model::channel::{Message, ReactionType},
+    model::channel::{Message, ReactionType, AttachmentType},
+    builder::CreateEmbed,
+    http::HttpError,
     async_trait,
 };
 
@@ -52,6 +59,60 @@ fn react_fail(ctx: &Context, message: &Message) {
     });
 }
 
+// Discord won't take a message longer than this
+const MESSAGE_LIMIT: usize = 2000;
+// Or embed titles and descriptions longer than these
+const EMBED_TITLE_LIMIT: usize = 256;
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+const EMBED_FIELD_LIMIT: usize = 1024;
+
+/// What to do with posts too long to fit in a single discord message
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LongPostMode {
+    // Break it up over as many messages as it takes
+    Split,
+    // Post a summary and attach the whole thing as a markdown file
+    Attach,
+}
+
+impl Default for LongPostMode {
+    fn default() -> Self {
+        LongPostMode::Split
+    }
+}
+
+/// A message discord didn't take, and whether sending it again could ever work
+#[derive(Debug)]
+pub struct SendError {
+    pub message: String,
+    pub permanent: bool,
+}
+
+impl SendError {
+    fn context(self, what: &str) -> SendError {
+        SendError { message: format!("{}: {}", what, self.message), permanent: self.permanent }
+    }
+}
+
+impl From<SerenityError> for SendError {
+    fn from(e: SerenityError) -> SendError {
+        // Discord saying no (missing permissions, a channel that's gone, a message it won't take)
+        // won't change by asking again, unless it's just telling us to slow down
+        let permanent = match &e {
+            SerenityError::Http(http) => match http.as_ref() {
+                HttpError::UnsuccessfulRequest(response) => {
+                    response.status_code.is_client_error() && response.status_code.as_u16() != 429
+                }
+                _ => false,
+            },
+            SerenityError::Model(_) => true,
+            _ => false,
+        };
+        SendError { message: e.to_string(), permanent: permanent }
+    }
+}
+
 struct BotEventHandler {
     listen_channel: ChannelId,
     parser: Parser,
@@ -95,15 +156,21 @@ pub struct DiscordBot {
     shard_handle: Option<futures_locks::Mutex<tokio::task::JoinHandle<()>>>,
     shard_cancel_token: CancellationToken,
     shard_manager: Arc<Mutex<ShardManager>>,
+    guild_id: GuildId,
     chat_channel: ChannelId,
     test_channel: ChannelId,
     archive_channel: ChannelId,
     audio_player: Arc<Mutex<AudioPlayer>>,
     command_parser: Parser,
+    long_posts: LongPostMode,
+    // Channels that get posts as embeds instead of plain text
+    embed_channels: Vec<u64>,
+    // Held while posting so split posts from different sources don't interleave
+    post_lock: Arc<Mutex<()>>,
 }
 
 impl DiscordBot {
-    pub async fn new(secrets: Secrets) -> DiscordBot {
+    pub async fn new(secrets: Secrets, archive: Archive) -> DiscordBot {
         info!("Created the discord bot");
         // Configure the client with your Discord bot token in the environment.
         let token = secrets.bot_token;
@@ -114,11 +181,12 @@ impl DiscordBot {
             secrets.audio_channel, 
             10,
             std::time::Duration::from_secs(60),
+            AudioSettings::open(std::path::PathBuf::from(&secrets.audio_settings_file)),
         ).await;
         warn!("Created audio player instance");
 
         // Create our command parser
-        let parser = Parser::new(audio_player_lock.clone()); // Give it the lock as it'll need to run audio commands
+        let parser = Parser::new(audio_player_lock.clone(), archive); // Give it the lock as it'll need to run audio commands
 
         // Create a new instance of the Client, logging in as a bot. This will
         // automatically prepend your bot token with "Bot ", which is a requirement
@@ -143,11 +211,15 @@ impl DiscordBot {
                 shard_handle: None,
                 shard_cancel_token: CancellationToken::new(),
                 shard_manager: manager_clone,
+                guild_id: GuildId(secrets.guild_id),
                 chat_channel: ChannelId(secrets.main_channel), // main channel
                 test_channel: ChannelId(secrets.test_channel),
                 archive_channel: ChannelId(secrets.archive_channel), // the archive channel
                 audio_player: audio_player_lock.clone(),
                 command_parser: parser,
+                long_posts: secrets.long_posts,
+                embed_channels: secrets.embed_channels.clone(),
+                post_lock: Arc::new(Mutex::new(())),
             };
 
         return bot;
@@ -222,23 +294,198 @@ impl DiscordBot {
         }
     }
 
-    pub async fn post_message(&self, message: SnifferPost) {
-        let http = &self.bot_http;
+    /// Announce a post, giving back the messages it went out as. Whatever gets sent is recorded in
+    /// the announcement as it goes, so trying again after a failure picks up where this left off
+    pub async fn post_message(&self, announcement: &mut Announcement) -> Result<Posted, SendError> {
+        let message = &announcement.post;
         info!("Trying to send message: {}", message);
-        let mut message_text = message.discord_string();
+        let message_text = message.discord_string();
+        // Keep the channels in order, even with several sources posting at once
+        let _post_guard = self.post_lock.lock().await;
+
+        // Send message to the source's channel, or our primary one if it didn't pick
+        let channel = match announcement.channel {
+            Some(c) => ChannelId(c),
+            None => self.chat_channel,
+        };
+        let main_id = self.send_post(channel, message, message_text.clone(), Vec::new(), announcement.mention_role, &mut announcement.discord.main).await
+            .map_err(|e| e.context("Error sending message to main channel"))?;
+        let main = MessageRef { channel: channel.0, message: main_id.0 };
+
+        // Send message to our archive channel with url attached, and a jump back to the announcement
+        let archive_id = self.send_post(self.archive_channel, message, message_text, self.archive_links(message, Some(main)), None, &mut announcement.discord.archive).await
+            .map_err(|e| e.context("Error sending message to archive"))?;
+        Ok(Posted {
+            main: main,
+            archive: MessageRef { channel: self.archive_channel.0, message: archive_id.0 },
+        })
+    }
+
+    /// Send some text to a channel, or the main one if none is given
+    pub async fn say(&self, channel: Option<u64>, text: String) -> Result<(), String> {
+        let channel = channel.map(ChannelId).unwrap_or(self.chat_channel);
+        let _post_guard = self.post_lock.lock().await;
+        self.send_split(channel, text, &mut ChannelProgress::default()).await
+            .map(|_| ())
+            .map_err(|e| e.message)
+    }
+
+    /// Put a post in the archive channel only, for when we're catching up on old posts
+    pub async fn post_archive(&self, post: &SnifferPost) -> Result<MessageId, String> {
+        let _post_guard = self.post_lock.lock().await;
+        self.send_post(self.archive_channel, post, post.discord_string(), self.archive_links(post, None), None, &mut ChannelProgress::default()).await
+            .map_err(|e| e.message)
+    }
+
+    // What the archive copy of a post links to: the post itself, and where we announced it if we did
+    fn archive_links(&self, post: &SnifferPost, main: Option<MessageRef>) -> Vec<String> {
+        let mut links: Vec<String> = post.url.clone().or(post.permalink.clone()).into_iter().collect();
+        if let Some(main) = main {
+            links.push(self.message_link(main));
+        }
+        links
+    }
+
+    fn message_link(&self, at: MessageRef) -> String {
+        format!("https://discord.com/channels/{}/{}/{}", self.guild_id, at.channel, at.message)
+    }
+
+    // Send a post however our long post setting says to, giving back the first message's id.
+    // Skips over whatever the progress says already went out
+    async fn send_post(&self, channel: ChannelId, post: &SnifferPost, text: String, links: Vec<String>, mention_role: Option<u64>, progress: &mut ChannelProgress) -> Result<MessageId, SendError> {
+        if progress.done {
+            if let Some(first) = progress.first {
+                return Ok(MessageId(first.message));
+            }
+        }
+        let mention = mention_role.map(|r| format!("<@&{}>\n", r)).unwrap_or_default();
+        // Once part of a split post is out, the rest has to follow as text
+        if self.embed_channels.contains(&channel.0) && progress.sent == 0 {
+            let http = &self.bot_http;
+            let sent = channel.send_message(&http, |m| {
+                if !mention.is_empty() {
+                    m.content(mention.trim_end());
+                }
+                m.embed(|e| build_embed(e, post, &links));
+                m
+            }).await;
+            match sent {
+                Ok(m) => return Ok(progress_sent(progress, channel, m.id)),
+                // Fall back to the plain text version if discord didn't like our embed
+                Err(e) => warn!("Failed to send post {} as an embed, sending as text: {}", post.id, e),
+            }
+        }
+        let link_line = link_lines(&links);
+        if self.long_posts == LongPostMode::Attach && progress.sent == 0 && text.chars().count() + link_line.chars().count() > MESSAGE_LIMIT {
+            warn!("Post {} is too long, attaching it as a file", post.id);
+            let summary = attach_summary(&mention, post, &link_line);
+            let http = &self.bot_http;
+            let sent = channel.send_message(&http, |m| {
+                m.content(summary);
+                m.add_file(AttachmentType::Bytes {
+                    data: Cow::from(text.into_bytes()),
+                    filename: format!("{}.md", post.fullname()),
+                });
+                m
+            }).await?;
+            return Ok(progress_sent(progress, channel, sent.id));
+        }
+        let text = format!("{}{}{}", mention, text, link_line);
+        self.send_split(channel, text, progress).await
+    }
 
-        // Send message to our primary channel
-        self.chat_channel.say(&http, message_text.clone()).await.expect("Error sending message to main channel");
+    // Send text over as many messages as it needs, in order, giving back the first message's id.
+    // Chunks the progress says already went out get skipped, so a retry doesn't repeat them
+    async fn send_split(&self, channel: ChannelId, text: String, progress: &mut ChannelProgress) -> Result<MessageId, SendError> {
+        let http = &self.bot_http;
+        for chunk in split_message(&text, MESSAGE_LIMIT).into_iter().skip(progress.sent) {
+            let sent = channel.say(&http, chunk).await?;
+            progress.first.get_or_insert(MessageRef { channel: channel.0, message: sent.id.0 });
+            progress.sent += 1;
+        }
+        progress.done = true;
+        progress.first.map(|f| MessageId(f.message))
+            .ok_or(SendError { message: String::from("Tried to send an empty message"), permanent: true })
+    }
 
-        // Send message to our archive channel with url attached
-        // Append the post url to this one if we have it
-        match message.url { 
-            Some(m) => {
-                message_text.push_str(format!("\n<{}>", m).as_str());
+    // Rewrite a message we announced a post with, putting a note above it. Split posts only get
+    // their first message touched, the rest stay as they were
+    async fn edit_post(&self, at: MessageRef, post: &SnifferPost, note: &str, links: Vec<String>) -> Result<(), String> {
+        let http = &self.bot_http;
+        let channel = ChannelId(at.channel);
+        if self.embed_channels.contains(&at.channel) {
+            let edited = channel.edit_message(&http, MessageId(at.message), |m| {
+                m.content(note);
+                m.embed(|e| build_embed(e, post, &links));
+                m
+            }).await;
+            match edited {
+                Ok(_) => return Ok(()),
+                Err(e) => warn!("Failed to edit post {} as an embed, editing as text: {}", post.id, e),
             }
-            None => {}
         }
-        self.archive_channel.say(&http, message_text).await.expect("Error sending message to archive");
+        let text = post.discord_string();
+        let link_line = link_lines(&links);
+        let content = match self.long_posts == LongPostMode::Attach && text.chars().count() + link_line.chars().count() > MESSAGE_LIMIT {
+            // The file's still attached, just redo the summary
+            true => format!("{}\n{}", note, attach_summary("", post, &link_line)),
+            false => format!("{}\n{}{}", note, text, link_line),
+        };
+        channel.edit_message(&http, MessageId(at.message), |m| m.content(truncate(&content, MESSAGE_LIMIT))).await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Follow up on an edited or deleted post in the archive channel, and mark the original
+    /// announcements to match
+    pub async fn post_change(&self, change: PostChange) {
+        let post = change.post;
+        let (note, mut message_text) = match change.kind {
+            ChangeKind::Deleted { lifetime } => {
+                (
+                    format!("🗑️ **Deleted** on reddit after {}", format_lifetime(lifetime)),
+                    format!("**Deleted** after {}\n{}\n> /r/{}", format_lifetime(lifetime), post.title, post.subreddit),
+                )
+            }
+            ChangeKind::Edited { old_title, old_body } => {
+                let mut text = format!("**Edited**\n{}\n> /r/{}\n```diff\n", post.title, post.subreddit);
+                if old_title != post.title {
+                    text.push_str(&diff_lines(&old_title, &post.title));
+                }
+                let empty = String::new();
+                text.push_str(&diff_lines(
+                    old_body.as_ref().unwrap_or(&empty),
+                    post.body.as_ref().unwrap_or(&empty),
+                ));
+                text.push_str("```");
+                (String::from("*(edited on reddit)*"), text)
+            }
+        };
+        // Link back to where we announced it originally
+        if let Some(m) = post.archive_message {
+            message_text.push_str(format!(
+                "\nOriginal: {}",
+                self.message_link(MessageRef { channel: self.archive_channel.0, message: m })
+            ).as_str());
+        }
+        if let Some(main) = post.main_message {
+            message_text.push_str(format!("\nAnnounced: {}", self.message_link(main)).as_str());
+        }
+        let _post_guard = self.post_lock.lock().await;
+        if let Some(main) = post.main_message {
+            if let Err(e) = self.edit_post(main, &post, &note, Vec::new()).await {
+                error!("Error updating main channel message for {}: {}", post.fullname(), e);
+            }
+        }
+        if let Some(m) = post.archive_message {
+            let at = MessageRef { channel: self.archive_channel.0, message: m };
+            if let Err(e) = self.edit_post(at, &post, &note, self.archive_links(&post, post.main_message)).await {
+                error!("Error updating archive message for {}: {}", post.fullname(), e);
+            }
+        }
+        if let Err(e) = self.send_split(self.archive_channel, message_text, &mut ChannelProgress::default()).await {
+            error!("Error sending post change to archive: {}", e.message);
+        }
     }
 
     #[allow(dead_code)]
@@ -251,6 +498,230 @@ impl DiscordBot {
 
 
 
+// Note down a post that went out as a single message
+fn progress_sent(progress: &mut ChannelProgress, channel: ChannelId, id: MessageId) -> MessageId {
+    progress.first = Some(MessageRef { channel: channel.0, message: id.0 });
+    progress.sent = 1;
+    progress.done = true;
+    id
+}
+
+// Links to tack on under a post, kept from embedding themselves
+fn link_lines(links: &[String]) -> String {
+    links.iter().map(|l| format!("\n<{}>", l)).collect()
+}
+
+// What goes out in place of a post too long for a message, alongside the full thing as a file
+fn attach_summary(mention: &str, post: &SnifferPost, link_line: &str) -> String {
+    format!(
+        "{}{}\n> /r/{}\n*Too long for discord, full post attached*{}",
+        mention, post.title, post.subreddit, link_line
+    )
+}
+
+// Fill out an embed with everything we know about a post, plus any extra links to show
+fn build_embed<'a>(e: &'a mut CreateEmbed, post: &SnifferPost, links: &[String]) -> &'a mut CreateEmbed {
+    let title = match post.is_comment() {
+        true => format!("Comment in \"{}\"", post.title),
+        false => post.title.clone(),
+    };
+    e.title(truncate(&title, EMBED_TITLE_LIMIT));
+    if let Some(link) = post.permalink.as_ref().or(post.url.as_ref()) {
+        e.url(link);
+    }
+    if let Some(body) = &post.body {
+        e.description(truncate(body, EMBED_DESCRIPTION_LIMIT));
+    }
+    if !post.author.is_empty() {
+        e.author(|a| a.name(format!("/u/{}", post.author)).url(format!("https://www.reddit.com/user/{}", post.author)));
+    }
+    e.field("Subreddit", format!("/r/{}", post.subreddit), true);
+    e.field("Score", post.score, true);
+    if let Some(flair) = &post.flair {
+        e.field("Flair", flair, true);
+    }
+    // Link posts get their link shown, since the title goes to the comments
+    if let (Some(url), Some(permalink)) = (&post.url, &post.permalink) {
+        if url != permalink {
+            e.field("Link", url, false);
+        }
+    }
+    if !post.media.is_empty() {
+        let mut media = String::new();
+        for (i, url) in post.media.iter().enumerate() {
+            let line = format!("[{}]({})\n", i + 1, url);
+            // Fields max out at 1024 characters
+            if media.len() + line.len() > EMBED_FIELD_LIMIT {
+                break;
+            }
+            media.push_str(&line);
+        }
+        e.field(format!("Media ({})", post.media.len()), media, false);
+    }
+    // The embed already links to the post, only show whatever else there is
+    let extra: Vec<&str> = links.iter()
+        .filter(|l| Some(*l) != post.url.as_ref() && Some(*l) != post.permalink.as_ref())
+        .map(|l| l.as_str())
+        .collect();
+    if !extra.is_empty() {
+        e.field("Announced", truncate(&extra.join("\n"), EMBED_FIELD_LIMIT), false);
+    }
+    // Embeds can only show images, so skip over any videos for the picture
+    let image = post.media.iter()
+        .find(|u| !u.contains("v.redd.it") && !u.contains(".mp4"))
+        .or(post.thumbnail.as_ref());
+    // Keep nsfw previews behind the link
+    if let Some(image) = image.filter(|_| !post.nsfw) {
+        e.image(image);
+    }
+    if let Some(time) = Utc.timestamp_opt(post.timestamp as i64, 0).single() {
+        e.timestamp(time.to_rfc3339());
+    }
+    e
+}
+
+// Cut text down to a char limit, marking that we did
+fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(limit - 1).collect();
+    cut.push('…');
+    cut
+}
+
+// Break text up into pieces discord will accept. We try to split between paragraphs, then
+// sentences, then words, and only cut mid-word as a last resort. Code blocks cut in half get
+// closed off and reopened in the next piece so they still render
+fn split_message(text: &str, limit: usize) -> Vec<String> {
+    if text.chars().count() <= limit {
+        return vec![text.to_string()];
+    }
+    // Leave room to close and reopen a code block
+    let fence_room = "\n```".len();
+    let mut pieces = Vec::<String>::new();
+    let mut current = String::new();
+    for part in split_units(text, limit - fence_room * 2) {
+        if !current.is_empty() && current.chars().count() + part.chars().count() > limit - fence_room * 2 {
+            pieces.push(std::mem::take(&mut current));
+        }
+        current.push_str(&part);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+
+    // Fix up any code blocks we cut through
+    let mut in_code = false;
+    let mut chunks = Vec::<String>::new();
+    for piece in pieces {
+        // A fence on its own would go out as an empty code block, the next chunk opens or closes it anyway
+        let trimmed = piece.trim();
+        if trimmed.starts_with("```") && !trimmed.contains('\n') && trimmed.matches("```").count() == 1 {
+            in_code = !in_code;
+            continue;
+        }
+        let mut chunk = String::new();
+        if in_code {
+            chunk.push_str("```\n");
+        }
+        chunk.push_str(piece.trim_end());
+        if piece.matches("```").count() % 2 == 1 {
+            in_code = !in_code;
+        }
+        if in_code {
+            chunk.push_str("\n```");
+        }
+        if !chunk.trim().is_empty() {
+            chunks.push(chunk);
+        }
+    }
+    chunks
+}
+
+// Cut text into the biggest natural pieces that each fit under the limit, keeping their separators
+fn split_units(text: &str, limit: usize) -> Vec<String> {
+    // Paragraphs, then sentences and lines, then words
+    const SEPARATORS: &[&[&str]] = &[&["\n\n"], &[". ", "! ", "? ", "\n"], &[" "]];
+    fn split_with(text: &str, level: usize, limit: usize, out: &mut Vec<String>) {
+        if text.chars().count() <= limit {
+            out.push(text.to_string());
+            return;
+        }
+        if level >= SEPARATORS.len() {
+            // No natural break left, cut it wherever
+            let chars: Vec<char> = text.chars().collect();
+            for piece in chars.chunks(limit) {
+                out.push(piece.iter().collect());
+            }
+            return;
+        }
+        let mut start = 0;
+        let mut i = 0;
+        while i < text.len() {
+            if let Some(sep) = SEPARATORS[level].iter().find(|s| text[i..].starts_with(**s)) {
+                let end = i + sep.len();
+                split_with(&text[start..end], level + 1, limit, out);
+                start = end;
+                i = end;
+            }
+            else {
+                i += text[i..].chars().next().map_or(1, |c| c.len_utf8());
+            }
+        }
+        if start < text.len() {
+            split_with(&text[start..], level + 1, limit, out);
+        }
+    }
+    let mut out = Vec::new();
+    split_with(text, 0, limit, &mut out);
+    out
+}
+
+// Turns a post's lifetime into something like "2d 3h 14m"
+fn format_lifetime(lifetime: std::time::Duration) -> String {
+    let secs = lifetime.as_secs();
+    let (days, hours, minutes) = (secs / 86400, (secs % 86400) / 3600, (secs % 3600) / 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m {}s", minutes, secs % 60),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+// A line based diff in the format discord highlights for ```diff blocks
+fn diff_lines(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // Longest common subsequence table, lcs[i][j] is the lcs of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    // Walk the table, unchanged lines are left out to keep the message short
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push_str(format!("- {}\n", old[i]).as_str());
+            i += 1;
+        } else {
+            diff.push_str(format!("+ {}\n", new[j]).as_str());
+            j += 1;
+        }
+    }
+    diff
+}
+
 impl Clone for DiscordBot {
     fn clone(&self) -> Self {
         DiscordBot {
@@ -264,12 +735,96 @@ impl Clone for DiscordBot {
             },
             shard_cancel_token: self.shard_cancel_token.clone(),
             shard_manager: self.shard_manager.clone(),
+            guild_id: self.guild_id.clone(),
             chat_channel: self.chat_channel.clone(),
             test_channel: self.test_channel.clone(),
             archive_channel: self.archive_channel.clone(),
             audio_player: self.audio_player.clone(),
             command_parser: self.command_parser.clone(),
+            long_posts: self.long_posts,
+            embed_channels: self.embed_channels.clone(),
+            post_lock: self.post_lock.clone(),
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lengths(chunks: &[String]) -> Vec<usize> {
+        chunks.iter().map(|c| c.chars().count()).collect()
+    }
+
+    #[test]
+    fn short_messages_go_out_whole() {
+        assert_eq!(split_message("hello there", 20), vec!["hello there"]);
+    }
+
+    #[test]
+    fn splits_between_words() {
+        // 18 leaves 10 once there's room for closing and reopening a code block
+        let chunks = split_message("aaaa bbbb cccc dddd", 18);
+        assert_eq!(chunks, vec!["aaaa bbbb", "cccc dddd"]);
+        assert_eq!(lengths(&chunks), vec![9, 9]);
+    }
+
+    #[test]
+    fn code_blocks_get_reopened() {
+        let text = format!("intro\n\n```\n{}```\n\nafter", "let x = 1;\n".repeat(8));
+        let chunks = split_message(&text, 40);
+        assert_eq!(chunks, vec![
+            "intro\n\n```\nlet x = 1;\n```",
+            "```\nlet x = 1;\nlet x = 1;\n```",
+            "```\nlet x = 1;\nlet x = 1;\n```",
+            "```\nlet x = 1;\nlet x = 1;\n```",
+            "```\nlet x = 1;\n```\n\nafter",
+        ]);
+        assert_eq!(lengths(&chunks), vec![25, 29, 29, 29, 25]);
+        for chunk in chunks.iter() {
+            assert_eq!(chunk.matches("```").count() % 2, 0, "unclosed code block in {:?}", chunk);
+        }
+    }
+
+    #[test]
+    fn no_empty_code_blocks() {
+        let text = format!("```
+{}
+```", "x".repeat(50));
+        let chunks = split_message(&text, 30);
+        assert_eq!(chunks, vec![
+            format!("```
+{}
+```", "x".repeat(22)),
+            format!("```
+{}
+```", "x".repeat(22)),
+            format!("```
+{}
+```", "x".repeat(6)),
+        ]);
+    }
+
+    #[test]
+    fn cuts_multibyte_text_on_char_boundaries() {
+        // Nothing to break on, so it gets cut every limit characters, not bytes
+        let units = split_units(&"🦀".repeat(25), 10);
+        assert_eq!(lengths(&units), vec![10, 10, 5]);
+        assert!(units.iter().all(|u| u.chars().all(|c| c == '🦀')));
+
+        let chunks = split_message(&format!("é{}", "ü".repeat(30)), 20);
+        assert_eq!(lengths(&chunks), vec![12, 12, 7]);
+        assert_eq!(chunks.concat(), format!("é{}", "ü".repeat(30)));
+    }
+
+    #[test]
+    fn chunks_stay_under_the_limit() {
+        let text = "Some words here. And a sentence there!\n\n".repeat(200);
+        let chunks = split_message(&text, MESSAGE_LIMIT);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.chars().count() <= MESSAGE_LIMIT));
+        // Nothing gets lost along the way
+        let words = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<String>>();
+        assert_eq!(words(&chunks.join(" ")), words(&text));
+    }
+}
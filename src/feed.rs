@@ -0,0 +1,165 @@
+// A little http server handing out the sniffer's posts as an atom feed, for feed readers
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use chrono::{TimeZone, Utc};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use pulldown_cmark::{html, Event, Options, Parser};
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::archive::Archive;
+use crate::reddit::SnifferPost;
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_items() -> usize {
+    50
+}
+
+fn default_bind() -> String {
+    String::from("127.0.0.1")
+}
+
+/// Where to serve the feed and how much to put in it, as read from the secrets file
+#[derive(Deserialize, Debug, Clone)]
+pub struct FeedConfig {
+    #[serde(default = "default_port")]
+    pub port: u16,
+    // How many of the newest posts go in the feed
+    #[serde(default = "default_items")]
+    pub items: usize,
+    // Only this machine can read the feed unless this says otherwise, 0.0.0.0 opens it up to everyone
+    #[serde(default = "default_bind")]
+    pub bind: String,
+}
+
+/// Serve the feed until we're told to stop
+pub async fn serve(config: FeedConfig, archive: Archive, cancel: CancellationToken) {
+    let addr: SocketAddr = match format!("{}:{}", config.bind, config.port).parse() {
+        Ok(a) => a,
+        Err(e) => {
+            error!("Bad feed address {}:{}: {}", config.bind, config.port, e);
+            return;
+        }
+    };
+    let items = config.items;
+    let make_service = make_service_fn(move |_| {
+        let archive = archive.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| respond(request, archive.clone(), items)))
+        }
+    });
+    let server = match Server::try_bind(&addr) {
+        Ok(s) => s.serve(make_service),
+        Err(e) => {
+            error!("Couldn't start the feed server on {}: {}", addr, e);
+            return;
+        }
+    };
+    warn!("Serving the sniffer feed on http://{}/feed.xml", addr);
+    if let Err(e) = server.with_graceful_shutdown(cancel.cancelled()).await {
+        error!("Feed server died: {}", e);
+    }
+    warn!("Stopped serving the feed");
+}
+
+async fn respond(request: Request<Body>, archive: Archive, items: usize) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/") | (&Method::GET, "/feed.xml") => {
+            Response::builder()
+                .header(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")
+                .body(Body::from(atom_feed(&archive.latest(items))))
+        }
+        _ => {
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not found"))
+        }
+    };
+    Ok(response.unwrap())
+}
+
+fn rfc3339(timestamp: u64) -> String {
+    Utc.timestamp_opt(timestamp as i64, 0).single().unwrap_or_else(Utc::now).to_rfc3339()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// The post's body as html, along with any media it had. Goes from reddit's own markdown, and any
+// html someone typed into their post comes out as plain text
+fn render_html(post: &SnifferPost) -> String {
+    let mut html = String::new();
+    // Posts archived before we kept the original only have the discord version
+    if let Some(body) = post.raw_body.as_ref().or(post.body.as_ref()) {
+        let events = Parser::new_ext(body, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH)
+            .map(|event| match event {
+                Event::Html(text) | Event::InlineHtml(text) => Event::Text(text),
+                e => e,
+            });
+        html::push_html(&mut html, events);
+    }
+    for url in post.media.iter() {
+        html.push_str(&format!("<p><a href=\"{0}\">{0}</a></p>\n", escape(url)));
+    }
+    if let (Some(url), Some(permalink)) = (&post.url, &post.permalink) {
+        if url != permalink && post.media.is_empty() {
+            html.push_str(&format!("<p><a href=\"{0}\">{0}</a></p>\n", escape(url)));
+        }
+    }
+    html
+}
+
+fn atom_entry(post: &SnifferPost) -> String {
+    let link = post.permalink.clone().or(post.url.clone()).unwrap_or_default();
+    let title = match post.is_comment() {
+        true => format!("Comment in \"{}\"", post.title),
+        false => post.title.clone(),
+    };
+    format!(
+        "  <entry>\n\
+        \x20   <id>urn:reddit:{}</id>\n\
+        \x20   <title>{}</title>\n\
+        \x20   <updated>{}</updated>\n\
+        \x20   <author><name>{}</name></author>\n\
+        \x20   <link rel=\"alternate\" href=\"{}\"/>\n\
+        \x20   <category term=\"{}\" label=\"/r/{}\"/>\n\
+        \x20   <content type=\"html\">{}</content>\n\
+        \x20 </entry>\n",
+        post.fullname(),
+        escape(&title),
+        rfc3339(post.timestamp),
+        escape(&post.author),
+        escape(&link),
+        escape(&post.subreddit),
+        escape(&post.subreddit),
+        escape(&render_html(post)),
+    )
+}
+
+/// An atom feed of the given posts, newest first
+pub fn atom_feed(posts: &[SnifferPost]) -> String {
+    let updated = posts.iter().map(|p| p.timestamp).max().map(rfc3339).unwrap_or_else(|| Utc::now().to_rfc3339());
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        \x20 <id>urn:feo:sniffer</id>\n\
+        \x20 <title>FeO sniffer</title>\n\
+        \x20 <updated>{}</updated>\n",
+        updated
+    );
+    for post in posts {
+        feed.push_str(&atom_entry(post));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
@@ -1,6 +1,3 @@
-#[macro_use]
-extern crate lazy_static;
-
 use tokio::{
     signal,
     time::sleep,
@@ -8,7 +5,12 @@ use tokio::{
 };
 
 use std::env;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use futures::future::join_all;
+use tokio_util::sync::CancellationToken;
 
 #[macro_use]
 extern crate log;
@@ -20,6 +22,13 @@ mod reddit;
 mod discord;
 mod audio;
 mod commands;
+mod archive;
+mod rules;
+mod stats;
+mod feed;
+mod sinks;
+mod outbox;
+mod persist;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Secrets {
@@ -29,9 +38,72 @@ pub struct Secrets {
     audio_channel: u64,
     test_channel: u64,
     archive_channel: u64,
-    sniffer: String
+    // Kept around for old secrets files, gets watched as a user source
+    sniffer: Option<String>,
+    #[serde(default)]
+    sources: Vec<reddit::SourceConfig>,
+    // Where the scraper keeps what it's already seen
+    #[serde(default = "default_state_file")]
+    state_file: String,
+    // Split posts that are too long for discord, or attach them as a file
+    #[serde(default)]
+    long_posts: discord::LongPostMode,
+    // Channels to send posts to as embeds rather than plain text
+    #[serde(default)]
+    embed_channels: Vec<u64>,
+    // Our searchable copy of every post we've announced
+    #[serde(default = "default_archive_file")]
+    archive_file: String,
+    // Which posts get announced where
+    #[serde(default = "default_rules_file")]
+    rules_file: String,
+    // Reddit script app credentials, we scrape anonymously without them
+    reddit_auth: Option<reddit::AuthConfig>,
+    // Where the weekly top posts go, defaults to the main channel
+    digest_channel: Option<u64>,
+    // Serve an atom feed of the archive if this is set
+    feed: Option<feed::FeedConfig>,
+    // Other places sources can send posts to
+    #[serde(default)]
+    webhooks: Vec<sinks::WebhookConfig>,
+    // Where posts go when we couldn't deliver them
+    #[serde(default = "default_dead_letter_file")]
+    dead_letter_file: String,
+    // Posts waiting to be delivered get spooled here
+    #[serde(default = "default_outbox_file")]
+    outbox_file: String,
+    // Volumes and such for the audio player
+    #[serde(default = "default_audio_settings_file")]
+    audio_settings_file: String,
+}
+
+fn default_state_file() -> String {
+    String::from("./sniffer_state.json")
+}
+
+fn default_archive_file() -> String {
+    String::from("./sniffer_archive.jsonl")
+}
+
+fn default_rules_file() -> String {
+    String::from("./rules.yaml")
+}
+
+fn default_dead_letter_file() -> String {
+    String::from("./sniffer_dead_letters.jsonl")
 }
 
+fn default_outbox_file() -> String {
+    String::from("./sniffer_outbox.json")
+}
+
+fn default_audio_settings_file() -> String {
+    String::from("./audio_settings.json")
+}
+
+// How many times we try pulling a source's history before leaving it for the next restart
+const MAX_BACKFILL_ATTEMPTS: u32 = 5;
+
 #[tokio::main]
 async fn main() {
 
@@ -62,43 +134,69 @@ async fn main() {
     debug!("{:?}", secrets.clone());
 
 
-    let mut discord_bot = discord::DiscordBot::new(secrets.clone()).await;
+    let archive = archive::Archive::open(PathBuf::from(&secrets.archive_file));
+    let mut discord_bot = discord::DiscordBot::new(secrets.clone(), archive.clone()).await;
     discord_bot.start_shards(1).await;
     
 
-    // Clone discord bot to use in a thread
-    let discord_bot_clone = discord_bot.clone();
+    // Lets us stop the scraper tasks and feed server cleanly on shutdown
+    let scraper_cancel_token = CancellationToken::new();
+    if let Some(feed_config) = secrets.feed.clone() {
+        tokio::spawn(feed::serve(feed_config, archive.clone(), scraper_cancel_token.clone()));
+    }
     let mut run_token = None;
     if will_sniff {
+        // Gather up everything we've been told to watch
+        let mut sources = secrets.sources.clone();
+        if let Some(sniffer) = &secrets.sniffer {
+            sources.push(reddit::SourceConfig {
+                name: sniffer.clone(),
+                kind: reddit::SourceKind::User,
+                poll_interval: 45,
+                comments: false,
+                channel: None,
+                backfill: false,
+                backfill_replay: false,
+                backfill_replay_delay: 10,
+                sinks: vec![String::from(sinks::DISCORD_SINK)],
+            });
+        }
+        let rules = rules::Rules::load(PathBuf::from(&secrets.rules_file));
+        let outputs = sinks::Outputs::new(
+            discord_bot.clone(), secrets.webhooks.clone(), PathBuf::from(&secrets.dead_letter_file)
+        );
+        let outbox = outbox::Outbox::open(PathBuf::from(&secrets.outbox_file), outputs.names());
         // Create our api interfaces
-        let mut reddit = reddit::RedditScraper::new(secrets.sniffer.clone());
-        run_token = Some(tokio::spawn(async move {
-            warn!("Starting scraper thread");
-            loop {
-                // Check every X seconds
-                sleep(Duration::from_secs(45)).await;
-                match reddit.update() {
-                    Ok(message_opt) => {
-                        match message_opt {
-                            Some(messages) => {
-                                warn!("Got {} new messages", messages.len());
-                                //let lock = discord_bot_clone.read().await;
-                                for message in messages {
-                                    warn!("New sniffer message!:\n{}", message);
-                                    //lock.post_message(message).await;
-                                    discord_bot_clone.post_message(message).await;
-                                }    
-                            },
-                            None => {
-                                debug!("No new sniffer message");
-                            },
-                        }
-                    }
-                    Err(error) => {
-                        error!("Encountered an error\n{}\nskipping this loop", error);
-                    }
-                }
+        let reddit = reddit::RedditScraper::new(sources, PathBuf::from(&secrets.state_file), secrets.reddit_auth.clone()).await;
+        // Anything we found last time but never got out the door goes back in line
+        for announcement in reddit.pending_announcements().await {
+            let post_id = announcement.post.fullname();
+            if outbox.contains(&post_id) {
+                continue;
             }
+            match route(&rules, announcement) {
+                Some(a) => outbox.push(a),
+                None => reddit.mark_delivered(&post_id, None, None).await,
+            }
+        }
+        // Every source gets its own task so a slow one doesn't hold up the rest
+        let mut handles = Vec::new();
+        for source_index in 0..reddit.source_count() {
+            handles.push(tokio::spawn(run_source(
+                reddit.clone(), source_index, discord_bot.clone(), outbox.clone(), archive.clone(), rules.clone(), scraper_cancel_token.clone()
+            )));
+        }
+        // And so does every sink, so one that's down doesn't hold up delivery to the others
+        for sink in outputs.names() {
+            handles.push(tokio::spawn(run_sink(
+                sink, outbox.clone(), outputs.clone(), reddit.clone(), archive.clone(), scraper_cancel_token.clone()
+            )));
+        }
+        handles.push(tokio::spawn(run_recheck(reddit, discord_bot.clone(), archive.clone(), scraper_cancel_token.clone())));
+        handles.push(tokio::spawn(run_digest(discord_bot.clone(), archive.clone(), secrets.digest_channel, scraper_cancel_token.clone())));
+        run_token = Some(tokio::spawn(async move {
+            warn!("Starting scraper threads");
+            join_all(handles).await;
         }));
     }
     
@@ -115,6 +213,8 @@ async fn main() {
                 }
                 _ = wait_sigint() => {
                     warn!("Got SIGINT");
+                    // Stop scraping before we pull the bot out from under it
+                    scraper_cancel_token.cancel();
                     // Kill our shards
                     //discord_bot_clone.write().await.stop_shards().await;
                     discord_bot_clone.shutdown().await;
@@ -127,6 +227,8 @@ async fn main() {
             select! {
                 _ = wait_sigint() => {
                     warn!("Got SIGINT");
+                    // Stop the feed server
+                    scraper_cancel_token.cancel();
                     // Kill our shards
                     //discord_bot_clone.write().await.stop_shards().await;
                     discord_bot_clone.shutdown().await;
@@ -151,6 +253,319 @@ async fn main() {
 
 }
 
+// Polls a single source forever, until we're told to stop
+async fn run_source(
+    reddit: reddit::RedditScraper,
+    source_index: usize,
+    discord_bot: discord::DiscordBot,
+    outbox: outbox::Outbox,
+    archive: archive::Archive,
+    rules: rules::Rules,
+    cancel: CancellationToken,
+) {
+    // Pull in the source's history alongside polling if it wants it, a long replay shouldn't hold up new posts
+    let backfill = async {
+        if reddit.needs_backfill(source_index).await {
+            run_backfill(&reddit, source_index, &discord_bot, &archive, &rules, &cancel).await;
+        }
+    };
+    tokio::join!(backfill, poll_source(&reddit, source_index, &outbox, &archive, &rules, &cancel));
+    warn!("Stopped scraping source {}", source_index);
+}
+
+async fn poll_source(
+    reddit: &reddit::RedditScraper,
+    source_index: usize,
+    outbox: &outbox::Outbox,
+    archive: &archive::Archive,
+    rules: &rules::Rules,
+    cancel: &CancellationToken,
+) {
+    // How many times in a row reddit has given us trouble
+    let mut failures: u32 = 0;
+    loop {
+        // Wait until the source is due for a check
+        select! {
+            _ = cancel.cancelled() => break,
+            _ = sleep(reddit.next_poll_in(source_index).await) => {}
+        }
+        let result = select! {
+            _ = cancel.cancelled() => break,
+            r = reddit.update(source_index) => r,
+        };
+        match result {
+            Ok(message_opt) => {
+                failures = 0;
+                match message_opt {
+                    Some(messages) => {
+                        warn!("Got {} new messages", messages.len());
+                        for message in messages {
+                            warn!("New sniffer message!:\n{}", message.post);
+                            // Archive it now, so we have it whether or not it gets delivered
+                            archive.record(&message.post);
+                            let post_id = message.post.fullname();
+                            match route(rules, message) {
+                                Some(m) => outbox.push(m),
+                                None => {
+                                    warn!("Rules suppressed post {}", post_id);
+                                    reddit.mark_delivered(&post_id, None, None).await;
+                                }
+                            }
+                        }
+                    },
+                    None => {
+                        debug!("No new sniffer message");
+                    },
+                }
+            }
+            Err(e) => {
+                error!("Encountered an error\n{}\nskipping this loop", e);
+                // Back off before trying reddit again
+                failures += 1;
+                let delay = backoff_delay(failures, e.retry_after());
+                warn!("Failed {} times in a row, backing off for {:?}", failures, delay);
+                select! {
+                    _ = cancel.cancelled() => break,
+                    _ = sleep(delay) => {}
+                }
+            }
+        }
+    }
+}
+
+// Apply the rules to a new post, giving back nothing if they want it kept quiet
+fn route(rules: &rules::Rules, mut announcement: reddit::Announcement) -> Option<reddit::Announcement> {
+    let decision = rules.decide(&announcement.post);
+    if decision.suppress {
+        return None;
+    }
+    announcement.channel = decision.channel.or(announcement.channel);
+    announcement.mention_role = decision.mention_role;
+    Some(announcement)
+}
+
+// Works through the outbox in order for one sink, trying posts again until it takes them. Once
+// every sink is done with a post it's delivered
+async fn run_sink(
+    sink: String,
+    outbox: outbox::Outbox,
+    outputs: sinks::Outputs,
+    reddit: reddit::RedditScraper,
+    archive: archive::Archive,
+    cancel: CancellationToken,
+) {
+    loop {
+        let mut queued = match outbox.next_for(&sink) {
+            Some(q) => q,
+            None => {
+                select! {
+                    _ = cancel.cancelled() => break,
+                    _ = outbox.wait(&sink) => {}
+                }
+                continue;
+            }
+        };
+        // Still backing off from the last try
+        let retry_at = queued.failures.get(&sink).and_then(|f| f.retry_at);
+        if let Some(at) = retry_at.filter(|at| *at > Instant::now()) {
+            select! {
+                _ = cancel.cancelled() => break,
+                _ = sleep(at.saturating_duration_since(Instant::now())) => {}
+            }
+            continue;
+        }
+        let post_id = queued.announcement.post.fullname();
+        let result = outputs.deliver(&sink, &mut queued.announcement).await;
+        let retries = outputs.retries(&sink);
+        let finished = outbox.update(&post_id, |q| {
+            // Only the discord sink keeps track of what it's sent so far
+            if sink == sinks::DISCORD_SINK {
+                q.announcement.discord = queued.announcement.discord.clone();
+                // Hang on to the main message if that much made it out, follow-ups can still edit it
+                if let Some(main) = q.announcement.discord.main.first {
+                    q.announcement.post.main_message.get_or_insert(main);
+                }
+            }
+            let error = match result {
+                Ok(posted) => {
+                    if let Some(posted) = posted {
+                        q.announcement.post.set_posted(posted);
+                    }
+                    None
+                }
+                Err(e) if e.retryable => {
+                    let failure = q.failures.entry(sink.clone()).or_default();
+                    failure.attempts += 1;
+                    failure.last_error = e.message.clone();
+                    if failure.attempts <= retries {
+                        let delay = backoff_delay(failure.attempts, None);
+                        warn!("Couldn't deliver {} to {} ({} tries): {}, trying again in {:?}", post_id, sink, failure.attempts, e.message, delay);
+                        failure.retry_at = Some(Instant::now() + delay);
+                        return;
+                    }
+                    error!("Giving up on delivering {} to {} after {} tries", post_id, sink, failure.attempts);
+                    Some(e.message)
+                }
+                Err(e) => {
+                    error!("Giving up on delivering {} to {}: {}", post_id, sink, e.message);
+                    Some(e.message)
+                }
+            };
+            // Still counts as done if we gave up, or we'd just queue it up again next time we start
+            if let Some(e) = error {
+                outputs.dead_letter(&sink, &e, &q.announcement.post);
+            }
+            q.announcement.sinks.retain(|s| *s != sink);
+            q.failures.remove(&sink);
+        });
+        if let Some(done) = finished {
+            let post = &done.announcement.post;
+            reddit.mark_delivered(&post_id, post.main_message, post.archive_message).await;
+            archive.record(post);
+        }
+    }
+    warn!("Stopped delivering posts to {}", sink);
+}
+
+// Archive a source's whole history, and replay it to the archive channel if it wants, leaving out
+// anything the rules suppress. Runs next to the source's polling, so new posts don't wait on it
+async fn run_backfill(
+    reddit: &reddit::RedditScraper,
+    source_index: usize,
+    discord_bot: &discord::DiscordBot,
+    archive: &archive::Archive,
+    rules: &rules::Rules,
+    cancel: &CancellationToken,
+) {
+    let config = reddit.source_config(source_index).await;
+    let mut failures: u32 = 0;
+    let posts = loop {
+        let result = select! {
+            _ = cancel.cancelled() => return,
+            r = reddit.backfill(source_index) => r,
+        };
+        match result {
+            Ok(posts) => break posts,
+            Err(e) => {
+                error!("Encountered an error backfilling {}\n{}", config.name, e);
+                failures += 1;
+                // Some sources won't ever work, like suspended users. Try again next time we start
+                if failures >= MAX_BACKFILL_ATTEMPTS {
+                    error!("Giving up backfilling {} after {} tries", config.name, failures);
+                    return;
+                }
+                select! {
+                    _ = cancel.cancelled() => return,
+                    _ = sleep(backoff_delay(failures, e.retry_after())) => {}
+                }
+            }
+        }
+    };
+    for post in posts.iter() {
+        archive.record(post);
+    }
+    // Call it done before replaying, a replay cut short is better than a channel full of repeats
+    reddit.finish_backfill(source_index).await;
+    if config.backfill_replay {
+        warn!("Replaying {} backfilled posts from {} to the archive channel", posts.len(), config.name);
+        for mut post in posts {
+            // Anything we'd keep quiet about as a new post stays quiet in a replay too
+            if rules.decide(&post).suppress {
+                warn!("Rules suppressed replaying {}", post.fullname());
+                continue;
+            }
+            select! {
+                _ = cancel.cancelled() => return,
+                _ = sleep(Duration::from_secs(config.backfill_replay_delay)) => {}
+            }
+            match discord_bot.post_archive(&post).await {
+                Ok(archive_id) => {
+                    post.archive_message = Some(archive_id.0);
+                    archive.record(&post);
+                }
+                Err(e) => error!("Failed to replay {}: {}", post.fullname(), e),
+            }
+        }
+        warn!("Done replaying {}", config.name);
+    }
+}
+
+// Every so often look back to see if anything we posted got edited or deleted
+async fn run_recheck(reddit: reddit::RedditScraper, discord_bot: discord::DiscordBot, archive: archive::Archive, cancel: CancellationToken) {
+    let mut failures: u32 = 0;
+    loop {
+        select! {
+            _ = cancel.cancelled() => break,
+            _ = sleep(reddit.next_recheck_in()) => {}
+        }
+        let result = select! {
+            _ = cancel.cancelled() => break,
+            r = reddit.recheck() => r,
+        };
+        match result {
+            Ok(recheck) => {
+                failures = 0;
+                for post in recheck.sampled.iter() {
+                    archive.record(post);
+                }
+                for change in recheck.changes {
+                    warn!("Sniffer post changed!:\n{}", change.post);
+                    // Keep the archive up to date with edits and deletions
+                    archive.record(&change.post);
+                    discord_bot.post_change(change).await;
+                }
+            }
+            Err(e) => {
+                error!("Encountered an error rechecking posts\n{}", e);
+                failures += 1;
+                let delay = backoff_delay(failures, e.retry_after());
+                select! {
+                    _ = cancel.cancelled() => break,
+                    _ = sleep(delay) => {}
+                }
+            }
+        }
+    }
+    warn!("Stopped rechecking posts");
+}
+
+// Posts the week's top posts every monday
+async fn run_digest(discord_bot: discord::DiscordBot, archive: archive::Archive, channel: Option<u64>, cancel: CancellationToken) {
+    loop {
+        let now = unix_now();
+        let next = stats::next_digest_at(now);
+        select! {
+            _ = cancel.cancelled() => break,
+            _ = sleep(Duration::from_secs(next - now)) => {}
+        }
+        let since = next.saturating_sub(stats::WEEK);
+        warn!("Posting the weekly digest");
+        if let Err(e) = discord_bot.say(channel, stats::digest(archive.since(since), since)).await {
+            error!("Failed to post the weekly digest: {}", e);
+        }
+    }
+    warn!("Stopped posting digests");
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Exponential backoff with some jitter so we don't hammer reddit right as a limit resets.
+// If reddit told us how long to wait we listen to it instead
+fn backoff_delay(failures: u32, retry_after: Option<Duration>) -> Duration {
+    let base = match retry_after {
+        Some(d) => d,
+        None => {
+            let exponent = failures.saturating_sub(1).min(8);
+            Duration::from_secs(5 * 2u64.pow(exponent)).min(Duration::from_secs(15 * 60))
+        }
+    };
+    // Up to a quarter of the delay on top
+    let jitter_ms = rand::thread_rng().gen_range(0..=base.as_millis() as u64 / 4);
+    base + Duration::from_millis(jitter_ms)
+}
+
 async fn wait_token<T>(handle: tokio::task::JoinHandle<T>) {
     handle.await.unwrap();
 }
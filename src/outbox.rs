@@ -0,0 +1,135 @@
+// Posts waiting to go out, kept on disk so a restart or an outage doesn't lose them
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::persist::write_atomic;
+use crate::reddit::Announcement;
+
+/// A post in line to be delivered, and how that's been going for each sink. The announcement's
+/// sinks are the ones that haven't taken it yet
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Queued {
+    pub announcement: Announcement,
+    #[serde(default)]
+    pub failures: HashMap<String, Failure>,
+}
+
+/// How a sink has been doing with a post
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Failure {
+    pub attempts: u32,
+    pub last_error: String,
+    // Not before this, so one sink's backoff doesn't hold up the others. Gone after a restart,
+    // which is as good a time as any to try again
+    #[serde(skip)]
+    pub retry_at: Option<Instant>,
+}
+
+/// A first in first out queue of posts, spooled to a json file every time it changes. Each sink
+/// works through it in order on its own, so a slow or broken one only holds itself up.
+/// Clones share the same queue
+#[derive(Clone)]
+pub struct Outbox {
+    path: PathBuf,
+    queue: Arc<Mutex<VecDeque<Queued>>>,
+    // Pokes whichever sinks are waiting on an empty queue
+    notify: Arc<HashMap<String, Notify>>,
+}
+
+impl Outbox {
+    /// Load whatever was left in the spool last time, for delivering to the given sinks
+    pub fn open(path: PathBuf, sinks: Vec<String>) -> Outbox {
+        let mut queue = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str::<VecDeque<Queued>>(&contents) {
+                Ok(q) => {
+                    warn!("Loaded {} undelivered posts from {}", q.len(), path.display());
+                    q
+                }
+                Err(e) => {
+                    error!("Couldn't parse outbox spool {}, starting empty: {}", path.display(), e);
+                    VecDeque::new()
+                }
+            },
+            Err(_) => VecDeque::new(),
+        };
+        let notify: HashMap<String, Notify> = sinks.into_iter().map(|s| (s, Notify::new())).collect();
+        // Sinks can disappear from the config between runs, nothing would ever take their posts
+        for queued in queue.iter_mut() {
+            drop_unknown_sinks(&notify, &mut queued.announcement);
+        }
+        queue.retain(|q| !q.announcement.sinks.is_empty());
+        Outbox {
+            path: path,
+            queue: Arc::new(Mutex::new(queue)),
+            notify: Arc::new(notify),
+        }
+    }
+
+    /// Put a post at the back of the line
+    pub fn push(&self, mut announcement: Announcement) {
+        drop_unknown_sinks(&self.notify, &mut announcement);
+        if announcement.sinks.is_empty() {
+            return;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        for sink in announcement.sinks.iter() {
+            self.notify[sink].notify_one();
+        }
+        queue.push_back(Queued {
+            announcement: announcement,
+            failures: HashMap::new(),
+        });
+        self.save(&queue);
+    }
+
+    /// The oldest post a sink still has to take, without taking it out
+    pub fn next_for(&self, sink: &str) -> Option<Queued> {
+        self.queue.lock().unwrap().iter().find(|q| q.announcement.sinks.iter().any(|s| s == sink)).cloned()
+    }
+
+    /// Change a post that's in line, giving it back once no sinks are left to take it
+    pub fn update<F: FnOnce(&mut Queued)>(&self, fullname: &str, change: F) -> Option<Queued> {
+        let mut queue = self.queue.lock().unwrap();
+        let index = queue.iter().position(|q| q.announcement.post.fullname() == fullname)?;
+        change(&mut queue[index]);
+        let finished = match queue[index].announcement.sinks.is_empty() {
+            true => queue.remove(index),
+            false => None,
+        };
+        self.save(&queue);
+        finished
+    }
+
+    pub fn contains(&self, fullname: &str) -> bool {
+        self.queue.lock().unwrap().iter().any(|q| q.announcement.post.fullname() == fullname)
+    }
+
+    /// Wait until something gets pushed for a sink
+    pub async fn wait(&self, sink: &str) {
+        self.notify[sink].notified().await
+    }
+
+    fn save(&self, queue: &VecDeque<Queued>) {
+        let written = serde_json::to_string(queue).map_err(|e| e.to_string())
+            .and_then(|contents| write_atomic(&self.path, contents));
+        if let Err(e) = written {
+            error!("Failed to save outbox spool {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn drop_unknown_sinks(known: &HashMap<String, Notify>, announcement: &mut Announcement) {
+    let post_id = announcement.post.fullname();
+    announcement.sinks.retain(|s| {
+        if !known.contains_key(s) {
+            error!("No sink named {} for post {}, leaving it out", s, post_id);
+        }
+        known.contains_key(s)
+    });
+}
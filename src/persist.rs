@@ -0,0 +1,10 @@
+// Saving files so a crash partway through can't leave us with half of one
+use std::fs;
+use std::path::Path;
+
+/// Write to a temp file next to the real one and move it over, renames being atomic
+pub fn write_atomic(path: &Path, contents: String) -> Result<(), String> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents).map_err(|e| e.to_string())?;
+    fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+}
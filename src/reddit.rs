@@ -1,24 +1,324 @@
 // Formatting
 use std::fmt;
 
-// For our url regex matching
-use regex::Regex;
+// For turning reddit markdown into discord markdown
+mod markdown;
 
-// for our api request
-use reqwest;
-use reqwest::{Client, Error};
+// For source timing
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+// For reading sources out of the secrets file, and our state file
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug, Clone)]
+// Sources and the store get shared between polling tasks
+use std::sync::{Arc, Mutex};
+use tokio::sync::Mutex as AsyncMutex;
+use futures::future::join_all;
+
+mod store;
+use store::{PostStore, SourceState};
+mod error;
+pub use error::ScrapeError;
+mod ratelimit;
+mod api;
+use api::RedditApi;
+mod auth;
+pub use auth::AuthConfig;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SnifferPost {
     pub title: String,
     pub body: Option<String>,
+    // The body as reddit had it, before we made it fit for discord
+    #[serde(default)]
+    pub raw_body: Option<String>,
     pub subreddit: String,
     pub url: Option<String>,
     pub id: String,
     pub timestamp: u64,
+    #[serde(default)]
+    pub author: String,
+    // When we noticed the post was gone, so we only announce it once
+    #[serde(default)]
+    pub deleted_at: Option<u64>,
+    // Taken down by a mod (or reddit), which doesn't always blank the post out
+    #[serde(default)]
+    pub removed: bool,
+    // The message we announced this post with in the archive channel
+    #[serde(default)]
+    pub archive_message: Option<u64>,
+    // And the one in the main (or source's) channel
+    #[serde(default)]
+    pub main_message: Option<MessageRef>,
+    // Link to the post (or comment) itself on reddit
+    #[serde(default)]
+    pub permalink: Option<String>,
+    #[serde(default)]
+    pub kind: PostKind,
+    // Extra bits for the embed view, not every post has them
+    #[serde(default)]
+    pub score: i64,
+    #[serde(default)]
+    pub flair: Option<String>,
+    // The preview image if reddit made one, otherwise the thumbnail
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    #[serde(default)]
+    pub nsfw: bool,
+    // Every image or video attached to the post, galleries in order
+    #[serde(default)]
+    pub media: Vec<String>,
+    #[serde(default)]
+    pub upvote_ratio: f64,
+    #[serde(default)]
+    pub num_comments: i64,
+    // How the post did over its first few hours
+    #[serde(default)]
+    pub samples: Vec<Sample>,
+    // New posts we've found but haven't gotten out the door yet
+    #[serde(default)]
+    pub awaiting_delivery: bool,
+}
+
+/// A discord message, and the channel it's in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MessageRef {
+    pub channel: u64,
+    pub message: u64,
+}
+
+/// Where a post ended up once we announced it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Posted {
+    pub main: MessageRef,
+    pub archive: MessageRef,
+}
+
+/// A snapshot of how a post was doing at some point
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub at: u64,
+    pub score: i64,
+    pub upvote_ratio: f64,
+    pub num_comments: i64,
+}
+
+/// Whether a post is a submission, or a comment left somewhere
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PostKind {
+    Submission,
+    Comment {
+        link_id: String,
+        parent_id: String,
+        // The comment being replied to, if this isn't a top level comment
+        parent_author: Option<String>,
+        parent_body: Option<String>,
+    },
+}
+
+impl Default for PostKind {
+    fn default() -> Self {
+        PostKind::Submission
+    }
+}
+
+// Reddit's listing wrapper
+#[derive(Deserialize, Debug)]
+struct Listing<T> {
+    data: ListingData<T>,
+}
+#[derive(Deserialize, Debug)]
+struct ListingData<T> {
+    children: Vec<ListingChild<T>>,
+    // Cursor for the next page, if there is one
+    #[serde(default)]
+    after: Option<String>,
+}
+#[derive(Deserialize, Debug)]
+struct ListingChild<T> {
+    data: T,
+}
+
+// A submission as reddit gives it to us, only the bits we care about
+#[derive(Deserialize, Debug, Clone)]
+struct SubmissionData {
+    id: String,
+    title: String,
+    #[serde(default)]
+    selftext: String,
+    subreddit: String,
+    url: Option<String>,
+    #[serde(default)]
+    author: String,
+    #[serde(default)]
+    permalink: String,
+    created_utc: f64,
+    #[serde(default)]
+    score: i64,
+    #[serde(default)]
+    upvote_ratio: f64,
+    #[serde(default)]
+    num_comments: i64,
+    link_flair_text: Option<String>,
+    // Either an image url or one of reddit's placeholders like "self" or "nsfw"
+    thumbnail: Option<String>,
+    preview: Option<Preview>,
+    #[serde(default)]
+    over_18: bool,
+    // Galleries list their images in gallery_data, and the actual urls live in media_metadata
+    gallery_data: Option<GalleryData>,
+    media_metadata: Option<HashMap<String, MediaMetadata>>,
+    // Reddit hosted videos
+    media: Option<Media>,
+    #[serde(default)]
+    is_video: bool,
+    // Crossposts have an empty shell of a post, the real one is in here
+    crosspost_parent_list: Option<Vec<SubmissionData>>,
+    // Why the post was taken down, like "moderator" or "deleted", if it was
+    removed_by_category: Option<String>,
+    // Who took it down, only filled in when we can see that
+    removed_by: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct GalleryData {
+    #[serde(default)]
+    items: Vec<GalleryItem>,
+}
+#[derive(Deserialize, Debug, Clone)]
+struct GalleryItem {
+    media_id: String,
+}
+#[derive(Deserialize, Debug, Clone)]
+struct MediaMetadata {
+    // Missing if the image is still processing or failed
+    s: Option<MediaSource>,
+}
+#[derive(Deserialize, Debug, Clone)]
+struct MediaSource {
+    u: Option<String>,
+    gif: Option<String>,
+    mp4: Option<String>,
+}
+#[derive(Deserialize, Debug, Clone)]
+struct Media {
+    reddit_video: Option<RedditVideo>,
+}
+#[derive(Deserialize, Debug, Clone)]
+struct RedditVideo {
+    fallback_url: String,
+}
+
+// Hosts where the post url is the media itself
+const MEDIA_HOSTS: &[&str] = &["i.redd.it", "i.imgur.com", "v.redd.it"];
+const MEDIA_EXTENSIONS: &[&str] = &[".jpg", ".jpeg", ".png", ".gif", ".gifv", ".webp", ".mp4"];
+
+impl SubmissionData {
+    // Dig every bit of media out of the post, following crossposts back to the original
+    fn media_urls(&self) -> Vec<String> {
+        let mut urls = Vec::<String>::new();
+        if let (Some(gallery), Some(metadata)) = (&self.gallery_data, &self.media_metadata) {
+            for item in gallery.items.iter() {
+                let source = metadata.get(&item.media_id).and_then(|m| m.s.as_ref());
+                // Animated images have a gif or mp4 instead of a plain url
+                if let Some(url) = source.and_then(|s| s.u.as_ref().or(s.gif.as_ref()).or(s.mp4.as_ref())) {
+                    urls.push(markdown::decode_entities(url));
+                }
+            }
+        }
+        if let Some(video) = self.media.as_ref().and_then(|m| m.reddit_video.as_ref()) {
+            urls.push(markdown::decode_entities(&video.fallback_url));
+        }
+        if urls.is_empty() && !self.is_video {
+            if let Some(url) = self.url.as_ref().filter(|u| is_media_url(u)) {
+                urls.push(markdown::decode_entities(url));
+            }
+        }
+        if urls.is_empty() {
+            if let Some(parent) = self.crosspost_parent_list.as_ref().and_then(|l| l.first()) {
+                return parent.media_urls();
+            }
+        }
+        urls
+    }
+}
+
+fn is_media_url(url: &str) -> bool {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or_default().to_lowercase();
+    let host = path.trim_start_matches("https://").trim_start_matches("http://").split('/').next().unwrap_or_default().to_string();
+    MEDIA_HOSTS.contains(&host.as_str()) || MEDIA_EXTENSIONS.iter().any(|e| path.ends_with(e))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Preview {
+    #[serde(default)]
+    images: Vec<PreviewImage>,
+}
+#[derive(Deserialize, Debug, Clone)]
+struct PreviewImage {
+    source: PreviewSource,
+}
+#[derive(Deserialize, Debug, Clone)]
+struct PreviewSource {
+    url: String,
+}
+
+// A comment as reddit gives it to us, the link fields are missing from the info endpoint
+#[derive(Deserialize, Debug, Clone)]
+struct CommentData {
+    id: String,
+    #[serde(default)]
+    author: String,
+    #[serde(default)]
+    body: String,
+    subreddit: String,
+    link_id: String,
+    #[serde(default)]
+    link_title: String,
+    parent_id: String,
+    #[serde(default)]
+    permalink: String,
+    created_utc: f64,
+    #[serde(default)]
+    score: i64,
+}
+
+/// What happened to a post we already announced
+#[derive(Debug, Clone)]
+pub enum ChangeKind {
+    Deleted { lifetime: Duration },
+    Edited { old_title: String, old_body: Option<String> },
 }
 
+/// A change to a cached post, carrying the post as it looks now
+#[derive(Debug, Clone)]
+pub struct PostChange {
+    pub post: SnifferPost,
+    pub kind: ChangeKind,
+}
+
+/// Everything a recheck found: posts that changed, and posts that got a new score sample
+#[derive(Debug, Clone, Default)]
+pub struct Recheck {
+    pub changes: Vec<PostChange>,
+    pub sampled: Vec<SnifferPost>,
+}
+
+// How often we look back over cached posts for edits and deletions
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Posts older than this don't get rechecked anymore
+const RECHECK_MAX_AGE: u64 = 7 * 24 * 60 * 60;
+// How often we write down a post's score while it's young, and for how long
+const SAMPLE_INTERVAL: u64 = 30 * 60;
+const SAMPLE_WINDOW: u64 = 48 * 60 * 60;
+
 static APP_USER_AGENT: &str = concat!(
     env!("CARGO_PKG_NAME"),
     ":",
@@ -26,89 +326,437 @@ static APP_USER_AGENT: &str = concat!(
     " (I have no account)"
 );
 
-// TODO: Make sense of the timestamps, so that if the post is deleted we can post how long it took for luls
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
 impl SnifferPost {
-    pub fn from_roux(roux: roux::subreddit::responses::SubmissionsData) -> SnifferPost {
+    fn from_submission(submission: SubmissionData) -> SnifferPost {
         debug!("creating a new sniffer post object");
+        let media = submission.media_urls();
+        // Reddit escapes the preview urls like they're going into html
+        let preview = submission.preview
+            .and_then(|p| p.images.into_iter().next())
+            .map(|i| markdown::decode_entities(&i.source.url));
+        let thumbnail = submission.thumbnail.filter(|t| t.starts_with("http"));
         SnifferPost {
-            title: roux.title,
+            title: submission.title,
             body: {
-                if roux.selftext.is_empty() {
+                if submission.selftext.is_empty() {
                     None
                 }
                 else {
-                    Some(roux.selftext)
+                    Some(submission.selftext)
                 }
             },
-            subreddit: roux.subreddit,
-            url: roux.url,
-            id: roux.id,
-            timestamp: roux.created as u64,
+            raw_body: None,
+            subreddit: submission.subreddit,
+            url: submission.url,
+            id: submission.id,
+            timestamp: submission.created_utc as u64,
+            author: submission.author,
+            deleted_at: None,
+            removed: submission.removed_by_category.is_some() || submission.removed_by.is_some(),
+            archive_message: None,
+            main_message: None,
+            permalink: Some(format!("https://www.reddit.com{}", submission.permalink)),
+            kind: PostKind::Submission,
+            score: submission.score,
+            flair: submission.link_flair_text.filter(|f| !f.is_empty()),
+            thumbnail: preview.or(thumbnail),
+            nsfw: submission.over_18,
+            media: media,
+            upvote_ratio: submission.upvote_ratio,
+            num_comments: submission.num_comments,
+            samples: Vec::new(),
+            awaiting_delivery: false,
+        }
+    }
+
+    fn from_comment(comment: CommentData) -> SnifferPost {
+        debug!("creating a new sniffer comment object");
+        SnifferPost {
+            title: comment.link_title,
+            body: Some(comment.body),
+            raw_body: None,
+            subreddit: comment.subreddit,
+            url: None,
+            id: comment.id,
+            timestamp: comment.created_utc as u64,
+            author: comment.author,
+            deleted_at: None,
+            removed: false,
+            archive_message: None,
+            main_message: None,
+            permalink: Some(format!("https://www.reddit.com{}", comment.permalink)),
+            kind: PostKind::Comment {
+                link_id: comment.link_id,
+                parent_id: comment.parent_id,
+                parent_author: None,
+                parent_body: None,
+            },
+            score: comment.score,
+            flair: None,
+            thumbnail: None,
+            nsfw: false,
+            media: Vec::new(),
+            upvote_ratio: 0.0,
+            num_comments: 0,
+            samples: Vec::new(),
+            awaiting_delivery: false,
         }
     }
+
+    /// The id with reddit's type prefix, ids are only unique within a type
+    pub fn fullname(&self) -> String {
+        match self.kind {
+            PostKind::Submission => format!("t3_{}", self.id),
+            PostKind::Comment { .. } => format!("t1_{}", self.id),
+        }
+    }
+
+    pub fn is_comment(&self) -> bool {
+        matches!(self.kind, PostKind::Comment { .. })
+    }
+
+    /// Reddit doesn't drop deleted posts from the info endpoint, it blanks them out instead.
+    /// Link posts a mod removed keep everything, only the removal fields give them away
+    pub fn is_deleted(&self) -> bool {
+        self.removed
+            || self.author == "[deleted]"
+            || self.title == "[deleted by user]"
+            || matches!(self.body.as_deref(), Some("[deleted]") | Some("[removed]"))
+    }
     pub fn discord_string(&self) -> String {
+        if let PostKind::Comment { parent_author, parent_body, .. } = &self.kind {
+            return self.comment_discord_string(parent_author, parent_body);
+        }
         // If we have body text, use it
-        match &self.body {
+        let mut text = match &self.body {
             Some(b) => format!(
                 "{}\n\
                 \n\
                 {}\n\
                 > /r/{}", self.title, b, self.subreddit),
             None => format!("{}\n> /r/{}", self.title, self.subreddit)
+        };
+        // Bare links so discord embeds them for us
+        for url in self.media.iter() {
+            text.push_str(format!("\n{}", url).as_str());
         }
+        text
     }
 
-    pub fn format_urls(&mut self) {
-    //pub fn url_convert(mut self) -> SnifferPost {
-        // This is to ensure that this regex is only compiled once, so we aren't dropping
-        // it from scope and compiling it in a loop when this function runs over iterated objects
-        // this regex is compiled at runtime
-        lazy_static! {
-            // Returns the whole string to replace in the first capture, contents of [] in 2nd and () in 3rd
-            static ref RE: Regex = Regex::new(r"\[(.*)\]\((.*)\)").unwrap();
-        }
-
-        match self.body.as_ref() {
-            Some(body) => {
-                match RE.captures(body.as_str()) {
-                    Some(m) => {
-                        // We got a match
-                        let string_to_match = &m[0];
-                        let first_url = &m[1];
-                        let second_url = &m[1];
-                        //println!("{}\n{}\n{}", string_to_match, first_url, second_url);
-        
-                        // Just make extra sure, make sure the contents of the html formatting string are equal
-                        if first_url == second_url {
-                            // Replace our html url with a discord friendly one wrapped in <> tags
-                            let replace_string = format!("<{}>", first_url);
-                            self.body = Some(body.replace(string_to_match, replace_string.as_str()));
-                            warn!("Parsed url in post {}", self.id);
-                        }
-                        else {
-                            error!("Our two urls didn't match in the regex, something must be off");
-                        }
-                    }
-                    None => () // do nothing
-                }
+    // Comments get the thread they're in, and what they're replying to if it's another comment
+    fn comment_discord_string(&self, parent_author: &Option<String>, parent_body: &Option<String>) -> String {
+        let mut text = format!("Comment in \"{}\"\n", self.title);
+        if let (Some(author), Some(body)) = (parent_author, parent_body) {
+            // Keep the parent short, it's only there for context
+            let mut snippet: String = body.chars().take(300).collect();
+            if snippet.len() < body.len() {
+                snippet.push_str("...");
+            }
+            text.push_str(format!("> **{}**:\n", author).as_str());
+            for line in snippet.lines() {
+                text.push_str(format!("> {}\n", line).as_str());
             }
-            None => ()     
         }
-        //return self; // Give ourselves back whether we made changes or not
+        text.push_str(format!(
+            "\n\
+            {}\n\
+            > /r/{}", self.body.as_deref().unwrap_or_default(), self.subreddit).as_str());
+        text
+    }
+
+    /// Remember the messages a post was announced with
+    pub fn set_posted(&mut self, posted: Posted) {
+        self.main_message = Some(posted.main);
+        self.archive_message = Some(posted.archive.message);
+    }
+
+    /// Take on the numbers from a fresh copy of the post, and remember them
+    fn sample(&mut self, fresh: &SnifferPost, now: u64) {
+        self.score = fresh.score;
+        self.upvote_ratio = fresh.upvote_ratio;
+        self.num_comments = fresh.num_comments;
+        self.samples.push(Sample {
+            at: now,
+            score: fresh.score,
+            upvote_ratio: fresh.upvote_ratio,
+            num_comments: fresh.num_comments,
+        });
+    }
+
+    // Whether it's time to sample this post again
+    fn wants_sample(&self, now: u64) -> bool {
+        if now.saturating_sub(self.timestamp) > SAMPLE_WINDOW {
+            return false;
+        }
+        match self.samples.last() {
+            Some(s) => now.saturating_sub(s.at) >= SAMPLE_INTERVAL,
+            None => true,
+        }
+    }
+
+    /// Convert the post from reddit's markdown into discord's, keeping the original around
+    pub fn format_body(&mut self) {
+        self.title = markdown::decode_entities(&self.title);
+        if let Some(body) = &self.body {
+            self.raw_body = Some(markdown::decode_entities(body));
+            self.body = Some(markdown::to_discord(body));
+        }
     }
 }
 
 
-pub struct RedditScraper {
-    the_sniffer: String,
-    reqwest: reqwest::Client,
+/// What kind of reddit listing a source watches
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    User,
+    Subreddit,
+}
+
+fn default_poll_interval() -> u64 {
+    45
+}
+
+/// A single user or subreddit to watch, as read from the secrets file
+#[derive(Deserialize, Debug, Clone)]
+pub struct SourceConfig {
+    pub name: String,
+    pub kind: SourceKind,
+    // How often to check this source, in seconds
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+    // Also watch a user's comments, ignored for subreddits
+    #[serde(default)]
+    pub comments: bool,
+    // The discord channel new posts get announced in, defaults to the main channel
+    pub channel: Option<u64>,
+    // Pull the source's whole history into the archive the first time we see it
+    #[serde(default)]
+    pub backfill: bool,
+    // And post it all to the archive channel, this many seconds apart
+    #[serde(default)]
+    pub backfill_replay: bool,
+    #[serde(default = "default_replay_delay")]
+    pub backfill_replay_delay: u64,
+    // Where new posts get delivered, by sink name
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<String>,
+}
+
+fn default_sinks() -> Vec<String> {
+    vec![String::from("discord")]
+}
+
+fn default_replay_delay() -> u64 {
+    10
+}
+
+/// A new post, along with the channel its source wants it announced in
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Announcement {
+    pub channel: Option<u64>,
+    // A role to ping along with the post
+    pub mention_role: Option<u64>,
+    // The outputs it should go to
+    pub sinks: Vec<String>,
+    pub post: SnifferPost,
+    // How much of it has made it to discord, so a retry only sends what's left
+    #[serde(default)]
+    pub discord: DiscordProgress,
+}
+
+/// How far we got sending a post to each of our discord channels
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DiscordProgress {
+    pub main: ChannelProgress,
+    pub archive: ChannelProgress,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChannelProgress {
+    // The post's first message, once it's out
+    pub first: Option<MessageRef>,
+    // How many messages have gone out, split posts taking several
+    pub sent: usize,
+    pub done: bool,
+}
+
+// Per-source scraping state, each source keeps its own cache and timestamp
+struct RedditSource {
+    config: SourceConfig,
     last_post_timestamp: u64,
+    last_comment_timestamp: u64,
     post_cache: Vec<SnifferPost>,
+    last_poll: Option<Instant>,
+    backfill_pending: bool,
+}
+
+impl RedditSource {
+    fn new(config: SourceConfig) -> RedditSource {
+        RedditSource {
+            config: config,
+            last_post_timestamp: 0,
+            last_comment_timestamp: 0,
+            post_cache: Vec::new(),
+            last_poll: None,
+            backfill_pending: false,
+        }
+    }
+
+    fn listing_path(&self) -> String {
+        match self.config.kind {
+            SourceKind::User => format!("/user/{}/submitted.json", self.config.name),
+            SourceKind::Subreddit => format!("/r/{}/new.json", self.config.name),
+        }
+    }
+
+    fn comments_path(&self) -> Option<String> {
+        match self.config.kind {
+            SourceKind::User if self.config.comments => {
+                Some(format!("/user/{}/comments.json", self.config.name))
+            }
+            _ => None,
+        }
+    }
+
+    // The name we file this source's state under in the store
+    fn key(&self) -> String {
+        self.to_string()
+    }
+
+    fn state(&self) -> SourceState {
+        SourceState {
+            last_post_timestamp: self.last_post_timestamp,
+            last_comment_timestamp: self.last_comment_timestamp,
+            post_cache: self.post_cache.clone(),
+            backfill_pending: self.backfill_pending,
+        }
+    }
+
+    /// Seed our cache from a fetch, without announcing anything
+    fn seed(&mut self, mut posts: Vec<SnifferPost>) {
+        for post in posts.iter_mut() {
+            // Convert all our pulled posts to discord markdown for consistency
+            post.format_body();
+            if post.is_comment() {
+                self.last_comment_timestamp = self.last_comment_timestamp.max(post.timestamp);
+            }
+            else {
+                self.last_post_timestamp = self.last_post_timestamp.max(post.timestamp);
+            }
+        }
+        // Add our pulled posts to our cache
+        self.post_cache.append(&mut posts);
+        // First time we've seen this source, so this is when it gets its history pulled
+        self.backfill_pending = self.config.backfill;
+    }
+
+    /// Check fresh posts (or comments) against our cache, giving back the new ones and whether
+    /// our state changed. Submissions and comments each keep their own timestamp
+    fn take_new(&mut self, mut fresh_posts: Vec<SnifferPost>) -> (Vec<SnifferPost>, bool) {
+        // Our vec of potential new posts
+        let mut new_posts = Vec::<SnifferPost>::new();
+        // Whether we need to write anything back to the store
+        let mut changed = false;
+        let source_name = self.to_string();
+
+        // Check our new posts with our cache to see if any exist
+        for p in fresh_posts.iter_mut() {
+            let last_timestamp = match p.is_comment() {
+                true => &mut self.last_comment_timestamp,
+                false => &mut self.last_post_timestamp,
+            };
+            // we only need to check the new post timestamps against the last recorded one
+            if p.timestamp > *last_timestamp {
+                // Double-check to make sure that reddit didn't decide to "update" the timestamp on an older post
+                match self.post_cache.iter_mut().find(|x| *x == p) {
+                    Some(x) => { 
+                        error!("Reddit gave us an incorrectly modified timestamp on existing post {}", x.id);
+                        // update the post with the new timestamp, thanks reddit
+                        error!("Updating {} timestamp to {} from {}", x.id, x.timestamp, p.timestamp);
+                        x.timestamp = p.timestamp;
+                        // Update our last timestamp after correction
+                        *last_timestamp = p.timestamp;
+                        changed = true;
+                    }
+                    None => {
+                        debug!("New post {} from {}", p, source_name);
+                        // Convert the post body to discord markdown
+                        p.format_body();
+                        // record our new posts in the cache, until they're delivered
+                        let mut cached = p.clone();
+                        cached.awaiting_delivery = true;
+                        self.post_cache.push(cached);
+                        warn!("Cached a new post from {}", source_name);
+                        // Update the most recent timestamp 
+                        *last_timestamp = p.timestamp;
+                        changed = true;
+                        // Add our new posts
+                        new_posts.push(p.clone());
+                    },
+                }    
+            } // If there's no new post detected, we don't put any in our vec
+        }
+        (new_posts, changed)
+    }
+
+    /// Forget posts too old to be rechecked, giving back whether there were any. Anything still
+    /// waiting to go out stays put
+    fn prune(&mut self, now: u64) -> bool {
+        let before = self.post_cache.len();
+        self.post_cache.retain(|p| p.awaiting_delivery || now.saturating_sub(p.timestamp) < RECHECK_MAX_AGE);
+        self.post_cache.len() != before
+    }
+
+    fn announce(&self, post: SnifferPost) -> Announcement {
+        Announcement {
+            channel: self.config.channel,
+            mention_role: None,
+            sinks: self.config.sinks.clone(),
+            post: post,
+            discord: DiscordProgress::default(),
+        }
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.config.poll_interval)
+    }
+
+    // How long until this source wants to be polled again, zero if it's overdue
+    fn next_poll_in(&self) -> Duration {
+        match self.last_poll {
+            Some(t) => self.poll_interval().checked_sub(t.elapsed()).unwrap_or(Duration::ZERO),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+impl fmt::Display for RedditSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.config.kind {
+            SourceKind::User => write!(f, "/u/{}", self.config.name),
+            SourceKind::Subreddit => write!(f, "/r/{}", self.config.name),
+        }
+    }
+}
+
+/// Handle to every source we're watching. Clones share the same sources and store, so each
+/// source can be polled from its own task
+#[derive(Clone)]
+pub struct RedditScraper {
+    api: RedditApi,
+    sources: Vec<Arc<AsyncMutex<RedditSource>>>,
+    store: Arc<Mutex<PostStore>>,
+    last_recheck: Arc<Mutex<Instant>>,
 }
 
 impl PartialEq for SnifferPost {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+        self.fullname() == other.fullname()
     }
 }
 
@@ -120,139 +768,279 @@ impl fmt::Display for SnifferPost {
 
 impl RedditScraper {
 
-    pub fn new(sniffer: String) -> RedditScraper {
-        warn!("Creating the reddit scraper with user agent: {}", APP_USER_AGENT);
+    pub async fn new(sources: Vec<SourceConfig>, state_path: PathBuf, auth: Option<AuthConfig>) -> RedditScraper {
+        RedditScraper::with_api(sources, state_path, RedditApi::new(auth)).await
+    }
 
+    async fn with_api(sources: Vec<SourceConfig>, state_path: PathBuf, api: RedditApi) -> RedditScraper {
         let scraper = RedditScraper {
-            the_sniffer: sniffer,
-            reqwest: Client::builder()
-                .user_agent(APP_USER_AGENT)
-                //.connection_verbose(true)
-                //.use_native_tls()
-                .http1_title_case_headers()
-                .http2_prior_knowledge()
-                .http2_adaptive_window(true)
-                .build().expect("Error building reqwest client"),
-            last_post_timestamp: 0,
-            post_cache: Vec::new()
+            api: api,
+            sources: sources.into_iter().map(|c| Arc::new(AsyncMutex::new(RedditSource::new(c)))).collect(),
+            store: Arc::new(Mutex::new(PostStore::load(state_path))),
+            last_recheck: Arc::new(Mutex::new(Instant::now())),
         };
 
-        scraper.init()
+        // Get every source ready at the same time
+        join_all((0..scraper.sources.len()).map(|i| scraper.init_source(i))).await;
+        scraper
     }
 
-    fn init(mut self) -> RedditScraper {
+    async fn init_source(&self, source_index: usize) {
+        let mut source = self.sources[source_index].lock().await;
+        // If we've seen this source before, pick up where we left off so the next update
+        // announces anything that was posted while we were down
+        if let Some(state) = self.store.lock().unwrap().get(&source.key()) {
+            source.last_post_timestamp = state.last_post_timestamp;
+            source.last_comment_timestamp = state.last_comment_timestamp;
+            source.post_cache = state.post_cache.clone();
+            source.backfill_pending = state.backfill_pending;
+            warn!("Restored {} cached posts for {}", source.post_cache.len(), source);
+            return;
+        }
         // Get from reddit api
-        match self.pull_posts() {
-            Ok(mut p) => {
-                warn!("Got posts");
-                // Format the hyperlink text of all our pulled posts for consistency
-                for post in p.iter_mut() {
-                    post.format_urls();
-                }
+        source.last_poll = Some(Instant::now());
+        match self.pull_posts(source.listing_path(), source.comments_path()).await {
+            Ok(p) => {
+                warn!("Got posts for {}", source);
+                source.seed(p);
+                warn!("Pulled {} intial posts for {}", source.post_cache.len(), source);
+                self.save(&source);
+            }
+            Err(e) => {
+                error!("Got error seeding {} - {}", source, e);
+            }
+        }
+    }
+
+    fn save(&self, source: &RedditSource) {
+        self.store.lock().unwrap().update(source.key(), source.state());
+    }
+
+    pub fn source_count(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub async fn source_config(&self, source_index: usize) -> SourceConfig {
+        self.sources[source_index].lock().await.config.clone()
+    }
 
-                // Add our pulled posts to our cache
-                self.post_cache.append(&mut p);
+    /// How long until the given source is due to be checked
+    pub async fn next_poll_in(&self, source_index: usize) -> Duration {
+        self.sources[source_index].lock().await.next_poll_in()
+    }
 
-                // update our most recent timestamp
-                self.last_post_timestamp = self.post_cache.last().unwrap().timestamp;
+    /// How long until cached posts should be rechecked
+    pub fn next_recheck_in(&self) -> Duration {
+        RECHECK_INTERVAL.checked_sub(self.last_recheck.lock().unwrap().elapsed()).unwrap_or(Duration::ZERO)
+    }
 
-                warn!("Pulled {} intial posts", self.post_cache.len());
+    /// Mark a post as delivered, remembering which messages it went out with so follow-ups
+    /// can link to and edit them
+    pub async fn mark_delivered(&self, fullname: &str, main_message: Option<MessageRef>, archive_message: Option<u64>) {
+        for source_lock in self.sources.iter() {
+            let mut source = source_lock.lock().await;
+            if let Some(p) = source.post_cache.iter_mut().find(|p| p.fullname() == fullname) {
+                p.awaiting_delivery = false;
+                p.main_message = main_message.or(p.main_message);
+                p.archive_message = archive_message.or(p.archive_message);
+                self.save(&source);
+                return;
             }
-            Err(e) => {
-                error!("Got error (probably ratelimit) - {:?}", e);
-            } 
         }
+        error!("Tried to mark post {} delivered, but we don't have it cached", fullname);
+    }
 
-        return self;
+    /// Posts we found but never heard got delivered, say if we went down in between
+    pub async fn pending_announcements(&self) -> Vec<Announcement> {
+        let mut pending = Vec::<Announcement>::new();
+        for source_lock in self.sources.iter() {
+            let source = source_lock.lock().await;
+            for post in source.post_cache.iter().filter(|p| p.awaiting_delivery) {
+                let mut post = post.clone();
+                post.awaiting_delivery = false;
+                pending.push(source.announce(post));
+            }
+        }
+        pending.sort_by_key(|a| a.post.timestamp);
+        pending
     }
 
-    //fn pull_posts(&self) -> Result<Vec<SnifferPost>, RouxError> {
-    fn pull_posts(&self) -> Result<Vec<SnifferPost>, Error> {
-        // Get from reddit api
+    /// Look back over recently cached posts and report any that were edited or deleted, taking
+    /// score samples of the young ones while we're at it
+    pub async fn recheck(&self) -> Result<Recheck, ScrapeError> {
+        *self.last_recheck.lock().unwrap() = Instant::now();
+        let now = unix_now();
 
-        // what's required to run async in a sync function
-        let reddit_posts = tokio::task::block_in_place(move || {
-            tokio::runtime::Handle::current().block_on(async move {
-                //self.the_sniffer.submitted().await
-                let request = self.reqwest
-                    .get(format!("https://www.reddit.com/user/{}/submitted.json", self.the_sniffer));
-                let result = request.send().await.expect("Failed to get our request");
-                debug!("Response status: {:?}", result.status());
-                debug!("Reponse headers:\n{:?}", result.headers());
-                result.json::<roux::subreddit::responses::Submissions>().await
-            })
-        });
-        match reddit_posts {
-            Ok(submissions_data) => {
-                let mut new_posts = Vec::<SnifferPost>::new();
-                for p in submissions_data.data.children {
-                    new_posts.push(SnifferPost::from_roux(p.data));
+        // Everything young enough that we still care, and that we haven't already seen go away.
+        // Posts still in the outbox wait until they're out, there'd be no messages to change yet
+        let mut ids = Vec::<String>::new();
+        for source in self.sources.iter() {
+            ids.extend(source.lock().await.post_cache.iter()
+                .filter(|p| p.deleted_at.is_none() && !p.awaiting_delivery && now.saturating_sub(p.timestamp) < RECHECK_MAX_AGE)
+                .map(|p| p.fullname()));
+        }
+        debug!("Rechecking {} cached posts", ids.len());
+
+        let mut fresh_posts = self.api.get_info(&ids).await?;
+
+        let mut changes = Vec::<PostChange>::new();
+        let mut sampled = Vec::<SnifferPost>::new();
+        for source_lock in self.sources.iter() {
+            let mut source = source_lock.lock().await;
+            let mut changed = source.prune(now);
+            let source_name = source.to_string();
+            for cached in source.post_cache.iter_mut() {
+                if !ids.contains(&cached.fullname()) {
+                    continue;
+                }
+                // A post missing entirely from the info response is as gone as a blanked out one
+                let fresh = fresh_posts.iter_mut().find(|p| *p == cached);
+                let deleted = match &fresh {
+                    Some(p) => p.is_deleted(),
+                    None => true,
+                };
+                if deleted {
+                    warn!("Post {} from {} was deleted", cached.id, source_name);
+                    cached.deleted_at = Some(now);
+                    changes.push(PostChange {
+                        post: cached.clone(),
+                        kind: ChangeKind::Deleted {
+                            lifetime: Duration::from_secs(now.saturating_sub(cached.timestamp)),
+                        },
+                    });
+                    changed = true;
+                    continue;
+                }
+                let fresh = fresh.unwrap();
+                if cached.wants_sample(now) {
+                    cached.sample(fresh, now);
+                    sampled.push(cached.clone());
+                    changed = true;
+                }
+                // Comments from the info endpoint don't know their thread's title, keep ours
+                if fresh.is_comment() {
+                    fresh.title = cached.title.clone();
+                }
+                // Our cache holds formatted bodies, so format before comparing
+                fresh.format_body();
+                if fresh.title != cached.title || fresh.body != cached.body {
+                    warn!("Post {} from {} was edited", cached.id, source_name);
+                    let old_title = std::mem::replace(&mut cached.title, fresh.title.clone());
+                    let old_body = std::mem::replace(&mut cached.body, fresh.body.clone());
+                    cached.raw_body = fresh.raw_body.clone();
+                    changes.push(PostChange {
+                        post: cached.clone(),
+                        kind: ChangeKind::Edited {
+                            old_title: old_title,
+                            old_body: old_body,
+                        },
+                    });
+                    changed = true;
                 }
-                // Always sort our posts oldest->newest bc reddit just gives them in random order
-                new_posts.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap());
-                return Ok(new_posts);
             }
-            Err(error) => {
-                    error!("Encountered an error grabbing reddit posts\n{}", error);
-                    return Err(error)
-                },
+            if changed {
+                self.save(&source);
+            }
+        }
+        return Ok(Recheck {
+            changes: changes,
+            sampled: sampled,
+        });
+    }
+
+    /// Whether a source still needs its history pulled
+    pub async fn needs_backfill(&self, source_index: usize) -> bool {
+        self.sources[source_index].lock().await.backfill_pending
+    }
+
+    /// Page through everything a source has ever posted, oldest first. Nothing here gets
+    /// announced, it's meant for the archive
+    pub async fn backfill(&self, source_index: usize) -> Result<Vec<SnifferPost>, ScrapeError> {
+        // Don't hold onto the source while we page, this can take a while
+        let (path, comments_path, source_name) = {
+            let source = self.sources[source_index].lock().await;
+            (source.listing_path(), source.comments_path(), source.to_string())
         };
+        warn!("Backfilling history for {}", source_name);
+        let mut posts = self.api.get_all_submissions(path).await?;
+        if let Some(comments_path) = comments_path {
+            posts.extend(self.api.get_all_comments(comments_path).await?.into_iter().map(SnifferPost::from_comment));
+        }
+        for post in posts.iter_mut() {
+            post.format_body();
+        }
+        posts.sort_by_key(|p| p.timestamp);
+        // Pages can overlap if something gets posted while we're going through them
+        let mut seen = HashSet::new();
+        posts.retain(|p| seen.insert(p.fullname()));
+        warn!("Backfilled {} posts for {}", posts.len(), source_name);
+        Ok(posts)
+    }
+
+    /// Mark a source's history as pulled, so we don't do it again next time
+    pub async fn finish_backfill(&self, source_index: usize) {
+        let mut source = self.sources[source_index].lock().await;
+        source.backfill_pending = false;
+        self.save(&source);
+    }
+
+    //fn pull_posts(&self) -> Result<Vec<SnifferPost>, RouxError> {
+    async fn pull_posts(&self, listing_path: String, comments_path: Option<String>) -> Result<Vec<SnifferPost>, ScrapeError> {
+        // Get from reddit api
+        let mut posts = self.api.get_submissions(listing_path).await?;
+        // Comments come along for the ride if the source wants them
+        if let Some(comments_path) = comments_path {
+            let mut comments: Vec<SnifferPost> = self.api.get_comments(comments_path).await?
+                .into_iter()
+                .map(SnifferPost::from_comment)
+                .collect();
+            posts.append(&mut comments);
+        }
+        Ok(posts)
     }
-    
-    //pub fn update(&mut self) -> Result<Option<Vec<SnifferPost>>, RouxError> {
-    pub fn update(&mut self) -> Result<Option<Vec<SnifferPost>>, Error> {
 
-        debug!("Updating reddit posts");
+    /// Check a source for new posts
+    //pub fn update(&mut self) -> Result<Option<Vec<SnifferPost>>, RouxError> {
+    pub async fn update(&self, source_index: usize) -> Result<Option<Vec<Announcement>>, ScrapeError> {
 
-        // Strip the async requirement out of this function
-        //let posts_result = tokio::task::block_in_place(move || {
+        // Don't hold onto the source while we wait on reddit, everything else wanting it would wait too
+        let (listing_path, comments_path) = {
+            let mut source = self.sources[source_index].lock().await;
+            debug!("Updating reddit posts for {}", source);
+            source.last_poll = Some(Instant::now());
+            (source.listing_path(), source.comments_path())
+        };
 
-        let posts_result = self.pull_posts();
+        let posts_result = self.pull_posts(listing_path, comments_path).await;
 
         //let fresh_posts = match self.pull_posts().await {
-        let mut fresh_posts = match posts_result {
+        let fresh_posts = match posts_result {
             Ok(d) => d,
             Err(e) => return Err(e),
         };
 
-        // Our vec of potential new posts
-        let mut new_posts = Vec::<SnifferPost>::new();
+        let mut new_posts = {
+            let mut source = self.sources[source_index].lock().await;
+            let (new_posts, changed) = source.take_new(fresh_posts);
+            if changed {
+                self.save(&source);
+            }
+            new_posts
+        };
 
-        // Check our new posts with our cache to see if any exist
-        for p in fresh_posts.iter_mut() {
-            // we only need to check the new post timestamps against the last recorded one
-            if p.timestamp > self.last_post_timestamp {
-                // Double-check to make sure that reddit didn't decide to "update" the timestamp on an older post
-                match self.post_cache.iter_mut().find(|x| *x.id == p.id) {
-                    Some(x) => { 
-                        error!("Reddit gave us an incorrectly modified timestamp on existing post {}", x.id);
-                        // update the post with the new timestamp, thanks reddit
-                        error!("Updating {} timestamp to {} from {}", x.id, x.timestamp, p.timestamp);
-                        x.timestamp = p.timestamp;
-                        // Update our last_post_timestamp after correction
-                        self.last_post_timestamp = p.timestamp;
-                    }
-                    None => {
-                        debug!("New sniffer post {}", p);
-                        // Fix and urls in the post's body
-                        p.format_urls();
-                        // record our new posts in the cache
-                        self.post_cache.push(p.clone());
-                        warn!("Cached a new post");
-                        // Add our new posts
-                        new_posts.push(p.clone());
-                        // Update the most recent timestamp 
-                        self.last_post_timestamp = new_posts.last().unwrap().timestamp;
-                    },
-                }    
-            } // If there's no new post detected, we don't put any in our vec
+        if new_posts.is_empty() {
+            return Ok(None);
         }
 
-        if !new_posts.is_empty() {
-            // record our new posts in the cache
-            return Ok(Some(new_posts));
+        // Only bother looking up parent comments for the ones we're actually announcing
+        if let Err(e) = self.api.fill_parent_context(&mut new_posts).await {
+            error!("Couldn't get parent comments for context: {}", e);
         }
-        return Ok(None);
+        // Keep submissions and comments in the order they were made
+        new_posts.sort_by_key(|p| p.timestamp);
+
+        let source = self.sources[source_index].lock().await;
+        return Ok(Some(new_posts.into_iter().map(|p| source.announce(p)).collect()));
     }
 
 }
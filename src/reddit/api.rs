@@ -0,0 +1,222 @@
+// Everything that actually talks to reddit lives here
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use tokio::sync::Mutex as AsyncMutex;
+
+use super::auth::{Auth, AuthConfig, OAUTH_HOST};
+use super::ratelimit::RateLimit;
+use super::{CommentData, Listing, PostKind, ScrapeError, SnifferPost, SubmissionData, APP_USER_AGENT};
+
+// Reddit's info endpoint takes at most this many ids at a time
+pub const INFO_BATCH_SIZE: usize = 100;
+// The most a listing will give us per page
+const PAGE_SIZE: usize = 100;
+// Give up on reddit after this long, rather than waiting on a hung request forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Where anonymous requests go
+static ANONYMOUS_HOST: &str = "https://www.reddit.com";
+
+/// A cheaply cloneable handle to reddit, every clone shares the same rate limit
+#[derive(Clone)]
+pub struct RedditApi {
+    reqwest: Client,
+    // Everything we fetch is a path on here
+    base_url: String,
+    rate_limit: Arc<Mutex<RateLimit>>,
+    // Only there if we were given credentials, otherwise we stay anonymous
+    auth: Option<Arc<AsyncMutex<Auth>>>,
+}
+
+impl RedditApi {
+    pub fn new(auth_config: Option<AuthConfig>) -> RedditApi {
+        // Logged in requests have to go to the oauth host
+        let base_url = match auth_config {
+            Some(_) => OAUTH_HOST,
+            None => ANONYMOUS_HOST,
+        };
+        RedditApi::with_base_url(String::from(base_url), auth_config)
+    }
+
+    /// Point the api somewhere other than reddit, like a mock server
+    pub fn with_base_url(base_url: String, auth_config: Option<AuthConfig>) -> RedditApi {
+        let auth = auth_config.map(Auth::new);
+        // Reddit wants logged in scripts to say who they are
+        let user_agent = match auth.as_ref().and_then(|a| a.username()) {
+            Some(username) => format!("{}:{} (by /u/{})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), username),
+            None => String::from(APP_USER_AGENT),
+        };
+        match &auth {
+            Some(_) => warn!("Creating the reddit scraper with oauth, user agent: {}", user_agent),
+            None => warn!("Creating the anonymous reddit scraper with user agent: {}", user_agent),
+        }
+        let mut builder = Client::builder()
+            .user_agent(user_agent)
+            .timeout(REQUEST_TIMEOUT)
+            //.connection_verbose(true)
+            //.use_native_tls()
+            .http1_title_case_headers();
+        // We know reddit speaks http2, but we can't assume that about anything else
+        if base_url == OAUTH_HOST || base_url == ANONYMOUS_HOST {
+            builder = builder
+                .http2_prior_knowledge()
+                .http2_adaptive_window(true);
+        }
+        RedditApi {
+            reqwest: builder.build().expect("Error building reqwest client"),
+            base_url: base_url,
+            rate_limit: Arc::new(Mutex::new(RateLimit::default())),
+            auth: auth.map(|a| Arc::new(AsyncMutex::new(a))),
+        }
+    }
+
+    // Shared fetch for all of our json endpoints, takes a path on our base url
+    async fn get_json<T: DeserializeOwned>(&self, path: String) -> Result<T, ScrapeError> {
+        // Don't even bother if we know we're out of requests
+        self.rate_limit.lock().unwrap().check()?;
+        let request = self.reqwest.get(format!("{}{}", self.base_url, path));
+        let request = match &self.auth {
+            // Logged in requests need our token
+            Some(auth) => {
+                let token = auth.lock().await.token(&self.reqwest).await?;
+                request.bearer_auth(token)
+            }
+            None => request,
+        };
+        let result = request.send().await.map_err(ScrapeError::Request)?;
+        if result.status() == StatusCode::UNAUTHORIZED {
+            if let Some(auth) = &self.auth {
+                warn!("Reddit stopped taking our access token, getting a new one next time");
+                auth.lock().await.invalidate();
+            }
+        }
+        debug!("Response status: {:?}", result.status());
+        debug!("Reponse headers:\n{:?}", result.headers());
+        self.rate_limit.lock().unwrap().update(result.status(), result.headers())?;
+        if !result.status().is_success() {
+            return Err(ScrapeError::Status(result.status()));
+        }
+        result.json::<T>().await.map_err(ScrapeError::Parse)
+    }
+
+    /// Fetch anything that returns a listing of submissions, oldest first
+    pub async fn get_submissions(&self, path: String) -> Result<Vec<SnifferPost>, ScrapeError> {
+        let reddit_posts = self.get_json::<Listing<SubmissionData>>(path).await;
+        match reddit_posts {
+            Ok(submissions_data) => {
+                let mut new_posts = Vec::<SnifferPost>::new();
+                for p in submissions_data.data.children {
+                    new_posts.push(SnifferPost::from_submission(p.data));
+                }
+                // Always sort our posts oldest->newest bc reddit just gives them in random order
+                new_posts.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap());
+                return Ok(new_posts);
+            }
+            Err(error) => {
+                    error!("Encountered an error grabbing reddit posts\n{}", error);
+                    return Err(error)
+                },
+        };
+    }
+
+    /// Same as above, but for comments
+    pub async fn get_comments(&self, path: String) -> Result<Vec<CommentData>, ScrapeError> {
+        match self.get_json::<Listing<CommentData>>(path).await {
+            Ok(listing) => {
+                let mut comments: Vec<CommentData> = listing.data.children.into_iter().map(|c| c.data).collect();
+                comments.sort_by(|a, b| a.created_utc.partial_cmp(&b.created_utc).unwrap());
+                Ok(comments)
+            }
+            Err(error) => {
+                error!("Encountered an error grabbing reddit comments\n{}", error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Walk a listing all the way back using its after cursor, oldest first
+    pub async fn get_all_submissions(&self, path: String) -> Result<Vec<SnifferPost>, ScrapeError> {
+        let mut posts: Vec<SnifferPost> = self.get_all_pages::<SubmissionData>(path).await?
+            .into_iter()
+            .map(SnifferPost::from_submission)
+            .collect();
+        posts.sort_by_key(|p| p.timestamp);
+        Ok(posts)
+    }
+
+    /// Same as above, but for comments
+    pub async fn get_all_comments(&self, path: String) -> Result<Vec<CommentData>, ScrapeError> {
+        let mut comments = self.get_all_pages::<CommentData>(path).await?;
+        comments.sort_by(|a, b| a.created_utc.partial_cmp(&b.created_utc).unwrap());
+        Ok(comments)
+    }
+
+    async fn get_all_pages<T: DeserializeOwned>(&self, path: String) -> Result<Vec<T>, ScrapeError> {
+        let mut items = Vec::<T>::new();
+        let mut after: Option<String> = None;
+        loop {
+            let page_path = match &after {
+                Some(a) => format!("{}?limit={}&after={}", path, PAGE_SIZE, a),
+                None => format!("{}?limit={}", path, PAGE_SIZE),
+            };
+            let listing = self.get_json::<Listing<T>>(page_path).await?;
+            let count = listing.data.children.len();
+            items.extend(listing.data.children.into_iter().map(|c| c.data));
+            debug!("Got a page of {} from {}, {} so far", count, path, items.len());
+            // Reddit stops handing out cursors once it runs out of history (about 1000 items)
+            match listing.data.after {
+                Some(a) if count > 0 => after = Some(a),
+                _ => break,
+            }
+        }
+        Ok(items)
+    }
+
+    /// Look up posts and comments by fullname. The info endpoint hands back whatever type you
+    /// ask for, so submissions and comments get fetched separately
+    pub async fn get_info(&self, fullnames: &[String]) -> Result<Vec<SnifferPost>, ScrapeError> {
+        let (comment_ids, submission_ids): (Vec<&String>, Vec<&String>) = fullnames.iter().partition(|id| id.starts_with("t1_"));
+        let mut posts = Vec::<SnifferPost>::new();
+        for batch in submission_ids.chunks(INFO_BATCH_SIZE) {
+            let path = format!("/api/info.json?id={}", join_ids(batch));
+            posts.append(&mut self.get_submissions(path).await?);
+        }
+        for batch in comment_ids.chunks(INFO_BATCH_SIZE) {
+            let path = format!("/api/info.json?id={}", join_ids(batch));
+            for comment in self.get_comments(path).await? {
+                posts.push(SnifferPost::from_comment(comment));
+            }
+        }
+        Ok(posts)
+    }
+
+    /// Comments only know their parent's id, go get who they're replying to for context
+    pub async fn fill_parent_context(&self, posts: &mut Vec<SnifferPost>) -> Result<(), ScrapeError> {
+        let parent_ids: Vec<String> = posts.iter()
+            .filter_map(|p| match &p.kind {
+                PostKind::Comment { parent_id, .. } if parent_id.starts_with("t1_") => Some(parent_id.clone()),
+                _ => None,
+            })
+            .collect();
+        if parent_ids.is_empty() {
+            return Ok(());
+        }
+        let parents = self.get_info(&parent_ids).await?;
+        for post in posts.iter_mut() {
+            if let PostKind::Comment { parent_id, parent_author, parent_body, .. } = &mut post.kind {
+                if let Some(parent) = parents.iter().find(|c| c.fullname() == *parent_id) {
+                    *parent_author = Some(parent.author.clone());
+                    *parent_body = parent.body.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Turn a batch of fullnames into the comma separated list the info endpoint wants
+fn join_ids(ids: &[&String]) -> String {
+    ids.iter().map(|s| s.as_str()).collect::<Vec<&str>>().join(",")
+}
@@ -0,0 +1,92 @@
+// Logging in to reddit as a script app, which gets us a much bigger rate limit
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use serde::Deserialize;
+
+use super::ScrapeError;
+
+static TOKEN_URL: &str = "https://www.reddit.com/api/v1/access_token";
+/// Authenticated requests have to go here instead of www.reddit.com
+pub static OAUTH_HOST: &str = "https://oauth.reddit.com";
+// Grab a new token a little before the old one runs out
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Credentials for a reddit script app, as read from the secrets file. Without a username and
+/// password we log in as the app itself (client credentials), with them we log in as that user
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuthConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Holds onto our current access token, getting a fresh one whenever it's about to expire
+pub struct Auth {
+    config: AuthConfig,
+    token: Option<(String, Instant)>,
+}
+
+impl Auth {
+    pub fn new(config: AuthConfig) -> Auth {
+        Auth {
+            config: config,
+            token: None,
+        }
+    }
+
+    /// The user we're logged in as, if we're logged in as one
+    pub fn username(&self) -> Option<&String> {
+        self.config.username.as_ref().filter(|_| self.config.password.is_some())
+    }
+
+    /// Get a token we can use right now, refreshing it if we have to
+    pub async fn token(&mut self, client: &Client) -> Result<String, ScrapeError> {
+        if let Some((token, expires)) = &self.token {
+            if Instant::now() + REFRESH_MARGIN < *expires {
+                return Ok(token.clone());
+            }
+        }
+        let token = self.fetch_token(client).await?;
+        self.token = Some((token.access_token.clone(), Instant::now() + Duration::from_secs(token.expires_in)));
+        warn!("Got a new reddit access token, good for {}s", token.expires_in);
+        Ok(token.access_token)
+    }
+
+    /// Forget our token, for when reddit stops taking it early
+    pub fn invalidate(&mut self) {
+        self.token = None;
+    }
+
+    async fn fetch_token(&self, client: &Client) -> Result<TokenResponse, ScrapeError> {
+        let form = match (&self.config.username, &self.config.password) {
+            (Some(username), Some(password)) => vec![
+                ("grant_type", "password"),
+                ("username", username.as_str()),
+                ("password", password.as_str()),
+            ],
+            _ => vec![("grant_type", "client_credentials")],
+        };
+        let result = client.post(TOKEN_URL)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&form)
+            .send().await
+            .map_err(ScrapeError::Request)?;
+        if !result.status().is_success() {
+            return Err(ScrapeError::Auth(format!("token request failed with {}", result.status())));
+        }
+        // Reddit gives a 200 with an error body for bad credentials
+        let body = result.json::<serde_json::Value>().await.map_err(ScrapeError::Parse)?;
+        if let Some(error) = body.get("error") {
+            return Err(ScrapeError::Auth(format!("reddit rejected our credentials: {}", error)));
+        }
+        serde_json::from_value::<TokenResponse>(body).map_err(|e| ScrapeError::Auth(format!("bad token response: {}", e)))
+    }
+}
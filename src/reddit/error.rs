@@ -0,0 +1,43 @@
+// The ways talking to reddit can go wrong
+use std::fmt;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+#[derive(Debug)]
+pub enum ScrapeError {
+    // Couldn't get a response out of reddit at all
+    Request(reqwest::Error),
+    // Reddit wants us to slow down, and told us for how long
+    RateLimited { retry_after: Duration },
+    // Any other status we weren't expecting
+    Status(StatusCode),
+    // Got a response, but not one we could make sense of
+    Parse(reqwest::Error),
+    // Couldn't log in to reddit
+    Auth(String),
+}
+
+impl ScrapeError {
+    /// How long reddit asked us to wait, if it told us
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ScrapeError::RateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScrapeError::Request(e) => write!(f, "Request to reddit failed: {}", e),
+            ScrapeError::RateLimited { retry_after } => write!(f, "Rate limited by reddit, retry in {:?}", retry_after),
+            ScrapeError::Status(s) => write!(f, "Reddit responded with {}", s),
+            ScrapeError::Parse(e) => write!(f, "Couldn't parse reddit's response: {}", e),
+            ScrapeError::Auth(e) => write!(f, "Couldn't log in to reddit: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScrapeError {}
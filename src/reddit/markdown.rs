@@ -0,0 +1,640 @@
+// Converts reddit's flavour of markdown into something discord renders the same way.
+// Reddit and discord agree on bold, italics, strikethrough and inline code, everything else
+// either needs rewriting or escaping so discord doesn't make a mess of it
+
+// Characters discord treats as markdown, escapes for these have to survive the conversion
+const DISCORD_SPECIAL: &[char] = &['\\', '*', '_', '~', '`', '|', '>', ':'];
+
+// Zero width space, used to break up things discord would otherwise act on
+const ZWSP: char = '\u{200B}';
+
+const HORIZONTAL_RULE: &str = "──────────";
+
+/// Convert a reddit markdown body into discord friendly text
+pub fn to_discord(text: &str) -> String {
+    let text = decode_entities(text);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+
+        // Fenced code passes straight through, fence and all
+        if line.trim_start().starts_with("```") {
+            out.push(line.to_string());
+            i += 1;
+            while i < lines.len() {
+                out.push(lines[i].to_string());
+                i += 1;
+                if lines[i - 1].trim_start().starts_with("```") {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        // Indented code only counts at the start of a paragraph, discord wants it fenced
+        if is_indented_code(line) && out.last().map_or(true, |l| l.trim().is_empty()) {
+            out.push(String::from("```"));
+            while i < lines.len() {
+                let continues = is_indented_code(lines[i])
+                    || (lines[i].trim().is_empty() && i + 1 < lines.len() && is_indented_code(lines[i + 1]));
+                if !continues {
+                    break;
+                }
+                out.push(strip_indent(lines[i]).to_string());
+                i += 1;
+            }
+            out.push(String::from("```"));
+            continue;
+        }
+
+        // Discord has no tables, so line them up in a code block instead
+        if i + 1 < lines.len() && is_table_row(line) && is_table_separator(lines[i + 1]) {
+            let mut rows = vec![split_row(line)];
+            i += 2;
+            while i < lines.len() && is_table_row(lines[i]) {
+                rows.push(split_row(lines[i]));
+                i += 1;
+            }
+            out.push(render_table(rows));
+            continue;
+        }
+
+        out.push(convert_line(line));
+        i += 1;
+    }
+    out.join("\n")
+}
+
+/// Reddit hands us html escaped text in its json. The markdown underneath can have its own
+/// entities too (reddit's editor loves &#x200B;), so it takes two passes to get to plain text
+pub fn decode_entities(text: &str) -> String {
+    decode_once(&decode_once(text))
+}
+
+fn decode_once(text: &str) -> String {
+    text.replace("&#x200B;", "")
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        // Has to be last, or one pass would decode things like &amp;lt; twice
+        .replace("&amp;", "&")
+}
+
+fn is_indented_code(line: &str) -> bool {
+    (line.starts_with("    ") || line.starts_with('\t')) && !line.trim().is_empty()
+}
+
+fn strip_indent(line: &str) -> &str {
+    line.strip_prefix("    ").or_else(|| line.strip_prefix('\t')).unwrap_or(line)
+}
+
+fn is_horizontal_rule(line: &str) -> bool {
+    let trimmed: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    trimmed.len() >= 3
+        && (trimmed.chars().all(|c| c == '-') || trimmed.chars().all(|c| c == '*') || trimmed.chars().all(|c| c == '_'))
+}
+
+// Block level conversion for a single line that isn't code or a table
+fn convert_line(line: &str) -> String {
+    let trimmed = line.trim_start();
+
+    if is_horizontal_rule(line) {
+        return String::from(HORIZONTAL_RULE);
+    }
+
+    // Headings, reddit lets you skip the space after the #s
+    if trimmed.starts_with('#') {
+        let heading = trimmed.trim_start_matches('#').trim().trim_end_matches('#').trim();
+        if heading.is_empty() {
+            return String::new();
+        }
+        return format!("**{}**", convert_inline(heading));
+    }
+
+    // Quotes, discord needs a space after the > and can't nest them. Spoilers look similar but aren't quotes
+    if trimmed.starts_with('>') && !trimmed.starts_with(">!") {
+        let quoted = trimmed.trim_start_matches(|c: char| c == '>' || c == ' ');
+        return format!("> {}", convert_inline(quoted));
+    }
+
+    convert_inline(line)
+}
+
+// Inline conversion, links, spoilers, superscript, mentions and escaping
+fn convert_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+        let next = chars.get(i + 1).copied();
+        match c {
+            // Keep escapes discord understands, drop the ones it doesn't need
+            '\\' if next.is_some() => {
+                let escaped = next.unwrap();
+                if DISCORD_SPECIAL.contains(&escaped) {
+                    out.push('\\');
+                }
+                out.push(escaped);
+                i += 2;
+            }
+            // Inline code is left exactly as it is
+            '`' => {
+                let end = find_from(&chars, i + 1, |c| c == '`').unwrap_or(chars.len() - 1);
+                out.extend(&chars[i..=end]);
+                i = end + 1;
+            }
+            // Spoilers
+            '>' if next == Some('!') => {
+                match find_seq(&chars, i + 2, &['!', '<']) {
+                    Some(end) => {
+                        let inner: String = chars[i + 2..end].iter().collect();
+                        out.push_str(format!("||{}||", convert_inline(&inner)).as_str());
+                        i = end + 2;
+                    }
+                    None => {
+                        out.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            '[' => {
+                match parse_link(&chars, i) {
+                    Some((link_text, url, end)) => {
+                        out.push_str(format_link(&link_text, &url).as_str());
+                        i = end;
+                    }
+                    None => {
+                        out.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            '^' => {
+                let (inner, end) = if next == Some('(') {
+                    match find_closing(&chars, i + 1, '(', ')') {
+                        Some(close) => (chars[i + 2..close].iter().collect::<String>(), close + 1),
+                        None => (String::new(), i + 1),
+                    }
+                }
+                else {
+                    let end = find_from(&chars, i + 1, char::is_whitespace).unwrap_or(chars.len());
+                    (chars[i + 1..end].iter().collect::<String>(), end)
+                };
+                // Stacked carets just mean smaller superscript, discord has no such thing
+                let inner = inner.trim_start_matches('^');
+                // Nothing to raise (or a paren that never closes) means it was just a caret
+                if inner.is_empty() {
+                    out.push(c);
+                    i += 1;
+                    continue;
+                }
+                match superscript(inner) {
+                    Some(s) => out.push_str(s.as_str()),
+                    // If we can't do the whole thing, plain text looks better than half of it
+                    None if next == Some('(') => out.push_str(convert_inline(inner).as_str()),
+                    // A lone word keeps its caret, so faces like ^_^ come through
+                    None => {
+                        out.push(c);
+                        i += 1;
+                        continue;
+                    }
+                }
+                i = end;
+            }
+            // Bare urls go through untouched so we don't escape anything inside them
+            'h' if is_word_start(prev) && (starts_with(&chars, i, "https://") || starts_with(&chars, i, "http://")) => {
+                let end = find_from(&chars, i, char::is_whitespace).unwrap_or(chars.len());
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            // u/ and r/ mentions, underscores in names would otherwise turn into italics
+            'u' | 'r' | '/' if is_word_start(prev) => {
+                match parse_mention(&chars, i) {
+                    Some(end) => {
+                        let mention: String = chars[i..end].iter().collect();
+                        out.push_str(mention.replace('_', "\\_").as_str());
+                        i = end;
+                    }
+                    None => {
+                        out.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            // Reddit bolds __text__, discord would underline it
+            '_' if next == Some('_') && is_word_start(prev) => {
+                match find_seq(&chars, i + 2, &['_', '_']) {
+                    Some(end) if end > i + 2 => {
+                        let inner: String = chars[i + 2..end].iter().collect();
+                        out.push_str(format!("**{}**", convert_inline(&inner)).as_str());
+                        i = end + 2;
+                    }
+                    _ => {
+                        out.push_str("__");
+                        i += 2;
+                    }
+                }
+            }
+            // Reddit ignores underscores in the middle of words, discord doesn't
+            '_' if prev.map_or(false, char::is_alphanumeric) && next.map_or(false, char::is_alphanumeric) => {
+                out.push_str("\\_");
+                i += 1;
+            }
+            // Nobody gets pinged by a reddit post
+            '@' if starts_with(&chars, i + 1, "everyone") || starts_with(&chars, i + 1, "here") => {
+                out.push('@');
+                out.push(ZWSP);
+                i += 1;
+            }
+            '<' if next == Some('@') || next == Some('#') => {
+                out.push('<');
+                out.push(ZWSP);
+                i += 1;
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+fn is_word_start(prev: Option<char>) -> bool {
+    prev.map_or(true, |p| !p.is_alphanumeric() && p != '/' && p != '_')
+}
+
+fn starts_with(chars: &[char], start: usize, pattern: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    chars.len() >= start + pattern.len() && chars[start..start + pattern.len()] == pattern[..]
+}
+
+fn find_from(chars: &[char], start: usize, pred: impl Fn(char) -> bool) -> Option<usize> {
+    (start..chars.len()).find(|&i| pred(chars[i]))
+}
+
+fn find_seq(chars: &[char], start: usize, seq: &[char]) -> Option<usize> {
+    (start..chars.len()).find(|&i| chars[i..].starts_with(seq))
+}
+
+// Find the bracket closing the one at `start`, allowing nesting and escapes
+fn find_closing(chars: &[char], start: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+// [text](url "optional title"), gives back the text, url, and where the link ends
+fn parse_link(chars: &[char], start: usize) -> Option<(String, String, usize)> {
+    let text_end = find_closing(chars, start, '[', ']')?;
+    if chars.get(text_end + 1) != Some(&'(') {
+        return None;
+    }
+    let url_end = find_closing(chars, text_end + 1, '(', ')')?;
+    let text: String = chars[start + 1..text_end].iter().collect();
+    let target: String = chars[text_end + 2..url_end].iter().collect();
+    // Drop any title, and the angle brackets reddit allows around urls
+    let url = target.trim().split_whitespace().next().unwrap_or("");
+    let url = url.trim_start_matches('<').trim_end_matches('>').replace("\\)", ")").replace("\\(", "(");
+    if url.is_empty() {
+        return None;
+    }
+    Some((text, url, url_end + 1))
+}
+
+fn format_link(text: &str, url: &str) -> String {
+    // Relative links point back into reddit
+    let url = if url.starts_with('/') {
+        format!("https://www.reddit.com{}", url)
+    }
+    else {
+        url.to_string()
+    };
+    let text = text.trim();
+    let bare_url = url.trim_start_matches("https://").trim_start_matches("http://");
+    // No point repeating ourselves if the text is just the url
+    if text.is_empty() || text == url || text == bare_url {
+        return format!("<{}>", url);
+    }
+    // Wrapped in <> so discord doesn't embed every link in a post
+    format!("{} (<{}>)", convert_inline(text), url)
+}
+
+// Matches /u/name, u/name, /r/name and r/name, giving back where the mention ends
+fn parse_mention(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start;
+    if chars[i] == '/' {
+        i += 1;
+    }
+    if !matches!(chars.get(i), Some('u') | Some('r')) || chars.get(i + 1) != Some(&'/') {
+        return None;
+    }
+    i += 2;
+    let name_start = i;
+    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '-') {
+        i += 1;
+    }
+    if i == name_start {
+        return None;
+    }
+    Some(i)
+}
+
+// Discord can't do superscript, but unicode can for a lot of characters
+fn superscript(text: &str) -> Option<String> {
+    const NORMAL: &str = "0123456789+-=()abcdefghijklmnoprstuvwxyz";
+    const SUPER: &str = "⁰¹²³⁴⁵⁶⁷⁸⁹⁺⁻⁼⁽⁾ᵃᵇᶜᵈᵉᶠᵍʰⁱʲᵏˡᵐⁿᵒᵖʳˢᵗᵘᵛʷˣʸᶻ";
+    text.chars()
+        .map(|c| NORMAL.chars().position(|n| n == c).and_then(|p| SUPER.chars().nth(p)))
+        .collect()
+}
+
+fn is_table_row(line: &str) -> bool {
+    line.contains('|')
+}
+
+fn is_table_separator(line: &str) -> bool {
+    let cells = split_row(line);
+    !cells.is_empty() && cells.iter().all(|c| {
+        let c = c.trim().trim_start_matches(':').trim_end_matches(':');
+        !c.is_empty() && c.chars().all(|ch| ch == '-')
+    })
+}
+
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = if line.ends_with('|') && !line.ends_with("\\|") { &line[..line.len() - 1] } else { line };
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell.push('|');
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells.iter().map(|c| c.trim().to_string()).collect()
+}
+
+// Text inside a code block doesn't render markdown, so strip it down to what you'd read
+fn plain_text(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                out.push(chars[i + 1]);
+                i += 2;
+            }
+            '[' => {
+                match parse_link(&chars, i) {
+                    Some((text, _, end)) => {
+                        out.push_str(plain_text(&text).as_str());
+                        i = end;
+                    }
+                    None => {
+                        out.push('[');
+                        i += 1;
+                    }
+                }
+            }
+            '*' | '^' | '`' => i += 1,
+            '~' if chars.get(i + 1) == Some(&'~') => i += 2,
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+fn render_table(rows: Vec<Vec<String>>) -> String {
+    let rows: Vec<Vec<String>> = rows.iter().map(|r| r.iter().map(|c| plain_text(c)).collect()).collect();
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|col| rows.iter().filter_map(|r| r.get(col)).map(|c| c.chars().count()).max().unwrap_or(0))
+        .collect();
+
+    let render_row = |row: &Vec<String>| -> String {
+        (0..columns)
+            .map(|col| {
+                let cell = row.get(col).map(|c| c.as_str()).unwrap_or("");
+                format!("{}{}", cell, " ".repeat(widths[col] - cell.chars().count()))
+            })
+            .collect::<Vec<String>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut table = vec![String::from("```")];
+    for (i, row) in rows.iter().enumerate() {
+        table.push(render_row(row));
+        // Underline the header
+        if i == 0 {
+            table.push(widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<String>>().join("-+-"));
+        }
+    }
+    table.push(String::from("```"));
+    table.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_every_link() {
+        let body = "Check out [my site](https://example.com) and [the docs](https://docs.rs/regex).";
+        assert_eq!(
+            to_discord(body),
+            "Check out my site (<https://example.com>) and the docs (<https://docs.rs/regex>)."
+        );
+    }
+
+    #[test]
+    fn links_keep_parens_in_urls() {
+        let body = "[Rust](https://en.wikipedia.org/wiki/Rust_(programming_language))";
+        assert_eq!(to_discord(body), "Rust (<https://en.wikipedia.org/wiki/Rust_(programming_language)>)");
+    }
+
+    #[test]
+    fn link_with_url_as_text_collapses() {
+        let body = "[https://imgur.com/a/abc123](https://imgur.com/a/abc123)";
+        assert_eq!(to_discord(body), "<https://imgur.com/a/abc123>");
+    }
+
+    #[test]
+    fn relative_links_point_at_reddit() {
+        let body = "see [the wiki](/r/rust/wiki/faq)";
+        assert_eq!(to_discord(body), "see the wiki (<https://www.reddit.com/r/rust/wiki/faq>)");
+    }
+
+    #[test]
+    fn spoilers() {
+        let body = "The ending: >!he was dead the whole time!< wow";
+        assert_eq!(to_discord(body), "The ending: ||he was dead the whole time|| wow");
+    }
+
+    #[test]
+    fn quotes_are_not_spoilers() {
+        assert_eq!(to_discord(">quoted text"), "> quoted text");
+        assert_eq!(to_discord(">>nested quote"), "> nested quote");
+        assert_eq!(to_discord(">!spoiler!<"), "||spoiler||");
+    }
+
+    #[test]
+    fn superscript() {
+        assert_eq!(to_discord("x^2 + y^2"), "x² + y²");
+        assert_eq!(to_discord("^(edit: thanks for the gold)"), "edit: thanks for the gold");
+        assert_eq!(to_discord("^^tiny"), "ᵗⁱⁿʸ");
+    }
+
+    #[test]
+    fn carets_with_nothing_to_raise_stay() {
+        assert_eq!(to_discord("x ^ y"), "x ^ y");
+        assert_eq!(to_discord("2^"), "2^");
+        assert_eq!(to_discord("^_^ yay"), "^_^ yay");
+        assert_eq!(to_discord("^(unclosed"), "^(unclosed");
+    }
+
+    #[test]
+    fn double_underscores_are_bold() {
+        assert_eq!(to_discord("this is __important__ stuff"), "this is **important** stuff");
+        assert_eq!(to_discord("__Update__: fixed"), "**Update**: fixed");
+        assert_eq!(to_discord("snake__case"), "snake__case");
+    }
+
+    #[test]
+    fn headings() {
+        assert_eq!(to_discord("# Update"), "**Update**");
+        assert_eq!(to_discord("###Part 2###"), "**Part 2**");
+    }
+
+    #[test]
+    fn horizontal_rules() {
+        assert_eq!(to_discord("above\n\n***\n\nbelow"), format!("above\n\n{}\n\nbelow", HORIZONTAL_RULE));
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(to_discord("Tom &amp; Jerry &lt;3"), "Tom & Jerry <3");
+        assert_eq!(to_discord("para\n\n&#x200B;\n\npara"), "para\n\n\n\npara");
+    }
+
+    #[test]
+    fn escapes() {
+        // Discord understands escaped asterisks, but has no use for escaped carets or brackets
+        assert_eq!(to_discord(r"\*not bold\* 2\^3 \[brackets\]"), r"\*not bold\* 2^3 [brackets]");
+    }
+
+    #[test]
+    fn mentions_are_escaped() {
+        assert_eq!(to_discord("thanks u/some_user_name"), r"thanks u/some\_user\_name");
+        assert_eq!(to_discord("crosspost from /r/rust_gamedev"), r"crosspost from /r/rust\_gamedev");
+        assert_eq!(to_discord("snake_case_words"), r"snake\_case\_words");
+    }
+
+    #[test]
+    fn urls_are_left_alone() {
+        assert_eq!(to_discord("https://example.com/some_path_here"), "https://example.com/some_path_here");
+    }
+
+    #[test]
+    fn no_pings() {
+        assert_eq!(to_discord("@everyone look"), "@\u{200B}everyone look");
+        assert_eq!(to_discord("&lt;@123456&gt;"), "<\u{200B}@123456>");
+    }
+
+    #[test]
+    fn inline_code_untouched() {
+        assert_eq!(to_discord("run `cargo build --release_mode` now"), "run `cargo build --release_mode` now");
+    }
+
+    #[test]
+    fn indented_code_gets_fenced() {
+        let body = "Here's the code:\n\n    fn main() {\n        println!(\"hi\");\n    }\n\nThat's it";
+        assert_eq!(
+            to_discord(body),
+            "Here's the code:\n\n```\nfn main() {\n    println!(\"hi\");\n}\n```\n\nThat's it"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let body = "|Item|Price|\n|:-|-:|\n|[Keyboard](https://example.com/kb)|$50|\n|Mouse pad|$5|";
+        assert_eq!(
+            to_discord(body),
+            "```\nItem      | Price\n----------+------\nKeyboard  | $50\nMouse pad | $5\n```"
+        );
+    }
+
+    // A few real post bodies, end to end
+    #[test]
+    fn real_update_post() {
+        let body = "**EDIT:** Found it, thanks everyone!\n\n\
+                    &amp;#x200B;\n\n\
+                    Lost my dog near the park on 5th &amp; Main. \
+                    Pics [here](https://imgur.com/a/Xy_Z12) and [here](https://imgur.com/a/Ab_C34).\n\n\
+                    ^(I'm on mobile, sorry for formatting)";
+        assert_eq!(
+            to_discord(body),
+            "**EDIT:** Found it, thanks everyone!\n\n\
+             \n\n\
+             Lost my dog near the park on 5th & Main. \
+             Pics here (<https://imgur.com/a/Xy_Z12>) and here (<https://imgur.com/a/Ab_C34>).\n\n\
+             I'm on mobile, sorry for formatting"
+        );
+    }
+
+    #[test]
+    fn real_review_post() {
+        let body = "#Review\n\n\
+                    Played through it twice. &gt;!The twist in chapter 3!&lt; got me.\n\n\
+                    |Category|Score|\n|---|---|\n|Story|9/10|\n|Gameplay|7/10|\n\n\
+                    ---\n\n\
+                    &gt;Best game of the year\n\n\
+                    Not quite, but close. Shoutout to u/the_dev_team and r/gaming";
+        assert_eq!(
+            to_discord(body),
+            format!(
+                "**Review**\n\n\
+                 Played through it twice. ||The twist in chapter 3|| got me.\n\n\
+                 ```\nCategory | Score\n---------+------\nStory    | 9/10\nGameplay | 7/10\n```\n\n\
+                 {}\n\n\
+                 > Best game of the year\n\n\
+                 Not quite, but close. Shoutout to u/the\\_dev\\_team and r/gaming",
+                HORIZONTAL_RULE
+            )
+        );
+    }
+}
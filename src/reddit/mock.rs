@@ -0,0 +1,138 @@
+// A tiny stand-in for reddit, so the scraper can be tested without the internet
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// What the mock hands back for a path
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl MockResponse {
+    pub fn json(body: String) -> MockResponse {
+        MockResponse {
+            status: 200,
+            headers: vec![(String::from("Content-Type"), String::from("application/json"))],
+            body: body,
+        }
+    }
+
+    pub fn status(status: u16) -> MockResponse {
+        MockResponse {
+            status: status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+}
+
+/// A local http server answering with whatever response we've set for each path, ignoring
+/// query strings. Anything we haven't set gets a 404
+pub struct MockReddit {
+    addr: SocketAddr,
+    routes: Arc<Mutex<HashMap<String, MockResponse>>>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl MockReddit {
+    pub async fn start() -> MockReddit {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Couldn't bind mock reddit");
+        let mock = MockReddit {
+            addr: listener.local_addr().unwrap(),
+            routes: Arc::new(Mutex::new(HashMap::new())),
+            requests: Arc::new(AtomicUsize::new(0)),
+        };
+        let routes = mock.routes.clone();
+        let requests = mock.requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                requests.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(respond(stream, routes.clone()));
+            }
+        });
+        mock
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn set(&self, path: &str, response: MockResponse) {
+        self.routes.lock().unwrap().insert(String::from(path), response);
+    }
+
+    /// How many requests we've been sent so far
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+async fn respond(mut stream: TcpStream, routes: Arc<Mutex<HashMap<String, MockResponse>>>) {
+    // We only ever get GETs, so the request is done at the first blank line
+    let mut request = Vec::<u8>::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let path = target.split('?').next().unwrap_or(target);
+
+    let response = routes.lock().unwrap().get(path).cloned().unwrap_or(MockResponse::status(404));
+    let mut raw = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status, response.body.len()
+    );
+    for (name, value) in response.headers.iter() {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str("\r\n");
+    raw.push_str(&response.body);
+    let _ = stream.write_all(raw.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// A submission with just enough filled in for the scraper
+pub fn submission(id: &str, title: &str, created: u64) -> Value {
+    json!({
+        "kind": "t3",
+        "data": {
+            "id": id,
+            "title": title,
+            "selftext": "",
+            "subreddit": "test",
+            "author": "someone",
+            "url": format!("https://example.com/{}", id),
+            "permalink": format!("/r/test/comments/{}/", id),
+            "created_utc": created as f64,
+            "score": 1,
+        }
+    })
+}
+
+/// Wrap things up the way reddit's listings do
+pub fn listing(children: Vec<Value>) -> String {
+    json!({
+        "kind": "Listing",
+        "data": {
+            "after": null,
+            "children": children,
+        }
+    }).to_string()
+}
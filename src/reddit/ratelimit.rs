@@ -0,0 +1,56 @@
+// Tracks reddit's rate limit headers so we stop before we get told off
+use std::time::{Duration, Instant};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+use super::ScrapeError;
+
+#[derive(Debug, Default)]
+pub struct RateLimit {
+    // Requests we have left in the current window
+    remaining: Option<f64>,
+    // When the current window rolls over
+    reset_at: Option<Instant>,
+}
+
+fn header_f64(headers: &HeaderMap, name: &str) -> Option<f64> {
+    headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok()
+}
+
+impl RateLimit {
+    /// Check whether we're allowed to make a request right now
+    pub fn check(&self) -> Result<(), ScrapeError> {
+        if let (Some(remaining), Some(reset_at)) = (self.remaining, self.reset_at) {
+            let now = Instant::now();
+            if remaining < 1.0 && reset_at > now {
+                return Err(ScrapeError::RateLimited { retry_after: reset_at - now });
+            }
+        }
+        Ok(())
+    }
+
+    /// Record what reddit told us about our limits, erroring if it turned us away
+    pub fn update(&mut self, status: StatusCode, headers: &HeaderMap) -> Result<(), ScrapeError> {
+        if let Some(remaining) = header_f64(headers, "x-ratelimit-remaining") {
+            self.remaining = Some(remaining);
+        }
+        let reset = header_f64(headers, "x-ratelimit-reset").map(Duration::from_secs_f64);
+        if let Some(reset) = reset {
+            self.reset_at = Some(Instant::now() + reset);
+        }
+        debug!("Rate limit remaining {:?}, resets in {:?}", self.remaining, reset);
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            // Prefer an explicit Retry-After, fall back to the window reset, and guess if we have neither
+            let retry_after = header_f64(headers, RETRY_AFTER.as_str())
+                .map(Duration::from_secs_f64)
+                .or(reset)
+                .unwrap_or(Duration::from_secs(60));
+            self.remaining = Some(0.0);
+            self.reset_at = Some(Instant::now() + retry_after);
+            return Err(ScrapeError::RateLimited { retry_after: retry_after });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+// Durable storage for the scraper, so we remember what we've seen across restarts
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::SnifferPost;
+use crate::persist::write_atomic;
+
+/// Everything we need to pick a source back up where we left off
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SourceState {
+    pub last_post_timestamp: u64,
+    #[serde(default)]
+    pub last_comment_timestamp: u64,
+    pub post_cache: Vec<SnifferPost>,
+    // Set until we've made it all the way back through the source's history
+    #[serde(default)]
+    pub backfill_pending: bool,
+}
+
+/// A json file holding the state of every source, keyed by the source's display name
+pub struct PostStore {
+    path: PathBuf,
+    sources: HashMap<String, SourceState>,
+}
+
+impl PostStore {
+    /// Load the store from disk, starting fresh if it doesn't exist or can't be read
+    pub fn load(path: PathBuf) -> PostStore {
+        let sources = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                match serde_json::from_str::<HashMap<String, SourceState>>(&contents) {
+                    Ok(s) => {
+                        warn!("Loaded state for {} sources from {}", s.len(), path.display());
+                        s
+                    }
+                    Err(e) => {
+                        error!("Couldn't parse state file {}, starting fresh: {}", path.display(), e);
+                        HashMap::new()
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("No state file at {} ({}), starting fresh", path.display(), e);
+                HashMap::new()
+            }
+        };
+        PostStore {
+            path: path,
+            sources: sources,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&SourceState> {
+        self.sources.get(key)
+    }
+
+    /// Record a source's state and flush everything to disk
+    pub fn update(&mut self, key: String, state: SourceState) {
+        self.sources.insert(key, state);
+        if let Err(e) = self.save() {
+            error!("Failed to save state file {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string(&self.sources).map_err(|e| e.to_string())?;
+        write_atomic(&self.path, contents)
+    }
+}
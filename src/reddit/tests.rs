@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::api::RedditApi;
+use super::mock::{listing, submission, MockReddit, MockResponse};
+use super::*;
+
+static SUBMITTED: &str = "/user/someone/submitted.json";
+
+// Every test gets its own state file so they don't step on each other
+fn state_path(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("feo_{}_{}.json", std::process::id(), test));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn scraper(mock: &MockReddit, test: &str) -> RedditScraper {
+    let config = SourceConfig {
+        name: String::from("someone"),
+        kind: SourceKind::User,
+        poll_interval: 45,
+        comments: false,
+        channel: None,
+        backfill: false,
+        backfill_replay: false,
+        backfill_replay_delay: 10,
+        sinks: vec![String::from("discord")],
+    };
+    RedditScraper::with_api(vec![config], state_path(test), RedditApi::with_base_url(mock.url(), None)).await
+}
+
+fn announced_ids(result: Option<Vec<Announcement>>) -> Vec<String> {
+    result.unwrap_or_default().into_iter().map(|a| a.post.id).collect()
+}
+
+#[tokio::test]
+async fn announces_only_new_posts() {
+    let mock = MockReddit::start().await;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("b", "second", 200), submission("a", "first", 100)])));
+    let reddit = scraper(&mock, "new_posts").await;
+
+    // Nothing new yet, the first fetch is just our baseline
+    assert_eq!(announced_ids(reddit.update(0).await.unwrap()), Vec::<String>::new());
+
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![
+        submission("d", "fourth", 400), submission("c", "third", 300), submission("b", "second", 200), submission("a", "first", 100),
+    ])));
+    // Oldest first, so they go out in the order they were made
+    assert_eq!(announced_ids(reddit.update(0).await.unwrap()), vec!["c", "d"]);
+    // And only once
+    assert_eq!(announced_ids(reddit.update(0).await.unwrap()), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn reordered_posts_arent_announced() {
+    let mock = MockReddit::start().await;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![
+        submission("c", "third", 300), submission("b", "second", 200), submission("a", "first", 100),
+    ])));
+    let reddit = scraper(&mock, "reordered").await;
+
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![
+        submission("a", "first", 100), submission("c", "third", 300), submission("b", "second", 200),
+    ])));
+    assert_eq!(announced_ids(reddit.update(0).await.unwrap()), Vec::<String>::new());
+
+    // A new post buried in the middle still gets found
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![
+        submission("b", "second", 200), submission("d", "fourth", 400), submission("a", "first", 100), submission("c", "third", 300),
+    ])));
+    assert_eq!(announced_ids(reddit.update(0).await.unwrap()), vec!["d"]);
+}
+
+#[tokio::test]
+async fn retimestamped_posts_get_corrected_not_announced() {
+    let mock = MockReddit::start().await;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("b", "second", 200), submission("a", "first", 100)])));
+    let reddit = scraper(&mock, "retimestamped").await;
+
+    // Reddit bumps an old post's timestamp past our latest one
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("b", "second", 250), submission("a", "first", 100)])));
+    assert_eq!(announced_ids(reddit.update(0).await.unwrap()), Vec::<String>::new());
+    {
+        let source = reddit.sources[0].lock().await;
+        assert_eq!(source.last_post_timestamp, 250);
+        assert_eq!(source.post_cache.iter().find(|p| p.id == "b").unwrap().timestamp, 250);
+        assert_eq!(source.post_cache.len(), 2);
+    }
+
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![
+        submission("c", "third", 300), submission("b", "second", 250), submission("a", "first", 100),
+    ])));
+    assert_eq!(announced_ids(reddit.update(0).await.unwrap()), vec!["c"]);
+}
+
+#[tokio::test]
+async fn old_posts_get_pruned_unless_undelivered() {
+    let mock = MockReddit::start().await;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("b", "second", 200), submission("a", "first", 100)])));
+    let reddit = scraper(&mock, "pruned").await;
+    // Both are too old to keep, only b still has to go out
+    let now = 200 + RECHECK_MAX_AGE;
+
+    let mut source = reddit.sources[0].lock().await;
+    source.post_cache.iter_mut().find(|p| p.id == "b").unwrap().awaiting_delivery = true;
+    assert!(source.prune(now));
+    assert_eq!(source.post_cache.iter().map(|p| p.id.as_str()).collect::<Vec<&str>>(), vec!["b"]);
+    // Nothing left to prune
+    assert!(!source.prune(now));
+}
+
+#[tokio::test]
+async fn moderator_removals_count_as_deleted() {
+    let mock = MockReddit::start().await;
+    let created = unix_now() - 60;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("a", "first", created)])));
+    let reddit = scraper(&mock, "mod_removed").await;
+
+    // A removed link post still has its title and author, only the removal fields say it's gone
+    let mut removed = submission("a", "first", created);
+    removed["data"]["removed_by_category"] = serde_json::json!("moderator");
+    mock.set("/api/info.json", MockResponse::json(listing(vec![removed])));
+    let recheck = reddit.recheck().await.unwrap();
+    assert_eq!(recheck.changes.len(), 1);
+    assert!(matches!(recheck.changes[0].kind, ChangeKind::Deleted { .. }));
+}
+
+#[tokio::test]
+async fn undelivered_posts_arent_rechecked() {
+    let mock = MockReddit::start().await;
+    let created = unix_now() - 60;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("a", "first", created)])));
+    let reddit = scraper(&mock, "undelivered_recheck").await;
+    reddit.sources[0].lock().await.post_cache[0].awaiting_delivery = true;
+
+    // Gone from reddit, but it hasn't gone out yet so there's nothing to mark
+    mock.set("/api/info.json", MockResponse::json(listing(vec![])));
+    let recheck = reddit.recheck().await.unwrap();
+    assert!(recheck.changes.is_empty());
+    assert!(reddit.sources[0].lock().await.post_cache[0].deleted_at.is_none());
+}
+
+#[tokio::test]
+async fn server_errors_come_back_as_errors() {
+    let mock = MockReddit::start().await;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("a", "first", 100)])));
+    let reddit = scraper(&mock, "errors").await;
+
+    mock.set(SUBMITTED, MockResponse::status(503));
+    match reddit.update(0).await {
+        Err(ScrapeError::Status(s)) => assert_eq!(s.as_u16(), 503),
+        other => panic!("Expected a status error, got {:?}", other.map(announced_ids)),
+    }
+
+    mock.set(SUBMITTED, MockResponse::json(String::from("<html>we're down</html>")));
+    assert!(matches!(reddit.update(0).await, Err(ScrapeError::Parse(_))));
+
+    // And we pick right back up once reddit does
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("b", "second", 200), submission("a", "first", 100)])));
+    assert_eq!(announced_ids(reddit.update(0).await.unwrap()), vec!["b"]);
+}
+
+#[tokio::test]
+async fn too_many_requests_uses_retry_after() {
+    let mock = MockReddit::start().await;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("a", "first", 100)])));
+    let reddit = scraper(&mock, "too_many_requests").await;
+
+    mock.set(SUBMITTED, MockResponse::status(429).header("Retry-After", "30"));
+    match reddit.update(0).await {
+        Err(e) => assert_eq!(e.retry_after(), Some(Duration::from_secs(30))),
+        Ok(_) => panic!("Expected to be rate limited"),
+    }
+}
+
+#[tokio::test]
+async fn stops_asking_when_out_of_requests() {
+    let mock = MockReddit::start().await;
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("a", "first", 100)])));
+    let reddit = scraper(&mock, "out_of_requests").await;
+
+    mock.set(SUBMITTED, MockResponse::json(listing(vec![submission("a", "first", 100)]))
+        .header("x-ratelimit-remaining", "0")
+        .header("x-ratelimit-reset", "60"));
+    assert!(reddit.update(0).await.is_ok());
+
+    // We know we're out, so reddit shouldn't even hear from us
+    let requests = mock.requests();
+    match reddit.update(0).await {
+        Err(ScrapeError::RateLimited { retry_after }) => assert!(retry_after <= Duration::from_secs(60)),
+        other => panic!("Expected to be rate limited, got {:?}", other.map(announced_ids)),
+    }
+    assert_eq!(mock.requests(), requests);
+}
@@ -0,0 +1,211 @@
+// Rules deciding where (and whether) sniffed posts get announced
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::reddit::SnifferPost;
+
+// A rule as written in the rules file. Every condition that's set has to match
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct RuleConfig {
+    name: String,
+    // Only match posts in these subreddits
+    subreddits: Vec<String>,
+    // Never match posts in these subreddits
+    exclude_subreddits: Vec<String>,
+    // Regexes to look for, use (?i) for case insensitive
+    title: Option<String>,
+    body: Option<String>,
+    nsfw: Option<bool>,
+    // What to do with a matching post
+    channel: Option<u64>,
+    mention_role: Option<u64>,
+    suppress: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    config: RuleConfig,
+    title: Option<Regex>,
+    body: Option<Regex>,
+}
+
+/// What the rules want done with a post
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Decision {
+    pub suppress: bool,
+    pub channel: Option<u64>,
+    pub mention_role: Option<u64>,
+}
+
+impl Rule {
+    fn new(config: RuleConfig) -> Result<Rule, String> {
+        let compile = |pattern: &Option<String>| -> Result<Option<Regex>, String> {
+            match pattern {
+                Some(p) => Regex::new(p).map(Some).map_err(|e| format!("Bad regex in rule {}: {}", config.name, e)),
+                None => Ok(None),
+            }
+        };
+        Ok(Rule {
+            title: compile(&config.title)?,
+            body: compile(&config.body)?,
+            config: config,
+        })
+    }
+
+    fn matches(&self, post: &SnifferPost) -> bool {
+        let in_list = |list: &Vec<String>| list.iter().any(|s| s.trim_start_matches("r/").eq_ignore_ascii_case(&post.subreddit));
+        if !self.config.subreddits.is_empty() && !in_list(&self.config.subreddits) {
+            return false;
+        }
+        if in_list(&self.config.exclude_subreddits) {
+            return false;
+        }
+        if let Some(title) = &self.title {
+            if !title.is_match(&post.title) {
+                return false;
+            }
+        }
+        if let Some(body) = &self.body {
+            // Against what the author wrote, not our discord markup of it
+            let text = post.raw_body.as_deref().or(post.body.as_deref()).unwrap_or_default();
+            if !body.is_match(text) {
+                return false;
+            }
+        }
+        if let Some(nsfw) = self.config.nsfw {
+            if post.nsfw != nsfw {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Every rule from the rules file, checked in order. The first one that matches a post decides
+/// what happens to it, and posts nothing matches get announced like normal
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Load rules from a yaml file, no file means no rules
+    pub fn load(path: PathBuf) -> Rules {
+        let file = match OpenOptions::new().read(true).open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!("No rules file at {} ({}), announcing everything", path.display(), e);
+                return Rules::default();
+            }
+        };
+        let rules_file: RulesFile = serde_yaml::from_reader(file).expect("Serde error deserializing rules");
+        let rules: Vec<Rule> = rules_file.rules.into_iter()
+            .map(|r| Rule::new(r).expect("Error loading rules"))
+            .collect();
+        warn!("Loaded {} sniffer rules from {}", rules.len(), path.display());
+        Rules {
+            rules: rules,
+        }
+    }
+
+    pub fn decide(&self, post: &SnifferPost) -> Decision {
+        match self.rules.iter().find(|r| r.matches(post)) {
+            Some(rule) => {
+                debug!("Post {} matched rule {}", post.id, rule.config.name);
+                Decision {
+                    suppress: rule.config.suppress,
+                    channel: rule.config.channel,
+                    mention_role: rule.config.mention_role,
+                }
+            }
+            None => Decision::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn post(subreddit: &str, title: &str, body: &str, nsfw: bool) -> SnifferPost {
+        serde_json::from_value(json!({
+            "title": title,
+            "body": body,
+            "subreddit": subreddit,
+            "url": null,
+            "id": "abc",
+            "timestamp": 100,
+            "nsfw": nsfw,
+        })).unwrap()
+    }
+
+    fn rules(configs: Vec<RuleConfig>) -> Rules {
+        Rules {
+            rules: configs.into_iter().map(|c| Rule::new(c).unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn nothing_matching_gets_announced() {
+        let rules = rules(vec![RuleConfig { subreddits: vec![String::from("rust")], suppress: true, ..Default::default() }]);
+        assert_eq!(rules.decide(&post("golang", "hi", "", false)), Decision::default());
+    }
+
+    #[test]
+    fn subreddit_lists() {
+        let rules = rules(vec![
+            RuleConfig { subreddits: vec![String::from("r/Rust")], exclude_subreddits: vec![String::from("rust")], channel: Some(1), ..Default::default() },
+            RuleConfig { subreddits: vec![String::from("r/Rust")], channel: Some(2), ..Default::default() },
+        ]);
+        // Excluded from the first, but the second still takes it
+        assert_eq!(rules.decide(&post("rust", "hi", "", false)).channel, Some(2));
+        assert_eq!(rules.decide(&post("golang", "hi", "", false)).channel, None);
+    }
+
+    #[test]
+    fn title_and_body_regexes() {
+        let rules = rules(vec![
+            RuleConfig { title: Some(String::from("(?i)^wts")), mention_role: Some(5), ..Default::default() },
+            RuleConfig { body: Some(String::from("price: \\d+")), suppress: true, ..Default::default() },
+        ]);
+        assert_eq!(rules.decide(&post("sub", "WTS keyboard", "", false)).mention_role, Some(5));
+        assert!(rules.decide(&post("sub", "keyboard", "price: 40", false)).suppress);
+        assert!(!rules.decide(&post("sub", "keyboard", "free", false)).suppress);
+    }
+
+    #[test]
+    fn body_regex_sees_the_raw_body() {
+        let rules = rules(vec![RuleConfig { body: Some(String::from("^>!")), suppress: true, ..Default::default() }]);
+        let mut spoiler = post("sub", "hi", "&gt;!spoiler!&lt;", false);
+        spoiler.format_body();
+        assert!(rules.decide(&spoiler).suppress);
+    }
+
+    #[test]
+    fn nsfw() {
+        let rules = rules(vec![RuleConfig { nsfw: Some(true), suppress: true, ..Default::default() }]);
+        assert!(rules.decide(&post("sub", "hi", "", true)).suppress);
+        assert!(!rules.decide(&post("sub", "hi", "", false)).suppress);
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let rules = rules(vec![
+            RuleConfig { name: String::from("first"), title: Some(String::from("news")), channel: Some(1), ..Default::default() },
+            RuleConfig { name: String::from("second"), channel: Some(2), suppress: true, ..Default::default() },
+        ]);
+        assert_eq!(rules.decide(&post("sub", "big news", "", false)), Decision { suppress: false, channel: Some(1), mention_role: None });
+        assert_eq!(rules.decide(&post("sub", "other", "", false)), Decision { suppress: true, channel: Some(2), mention_role: None });
+    }
+}
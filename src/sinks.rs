@@ -0,0 +1,250 @@
+// Everywhere a sniffed post can be delivered to, discord being just one of them
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serenity::async_trait;
+
+use crate::discord::DiscordBot;
+use crate::reddit::{Announcement, Posted, SnifferPost};
+
+/// The name of the built in sink that posts to our discord channels
+pub static DISCORD_SINK: &str = "discord";
+// Discord webhooks won't take more than this
+const DISCORD_WEBHOOK_LIMIT: usize = 2000;
+// Sinks that don't say otherwise get this many goes after the first
+const DEFAULT_RETRIES: u32 = 9;
+
+/// Something we can hand new posts to
+#[async_trait]
+pub trait OutputSink: Send + Sync {
+    /// Deliver a post, giving back the discord messages it went out as if this sink makes any
+    /// Anything it manages to send along the way gets noted in the announcement, so a retry can
+    /// skip over it
+    async fn deliver(&self, announcement: &mut Announcement) -> Result<Option<Posted>, SinkError>;
+
+    /// How many more times the outbox tries after a failed delivery, before dead lettering it
+    fn retries(&self) -> u32 {
+        DEFAULT_RETRIES
+    }
+}
+
+/// Why a delivery failed, and whether it's worth trying again
+#[derive(Debug)]
+pub struct SinkError {
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl SinkError {
+    fn retryable(message: String) -> SinkError {
+        SinkError { message: message, retryable: true }
+    }
+
+    fn fatal(message: String) -> SinkError {
+        SinkError { message: message, retryable: false }
+    }
+}
+
+/// Our discord channels, through the bot
+pub struct DiscordSink {
+    bot: DiscordBot,
+}
+
+#[async_trait]
+impl OutputSink for DiscordSink {
+    async fn deliver(&self, announcement: &mut Announcement) -> Result<Option<Posted>, SinkError> {
+        // Retries only send what didn't make it, the bot keeps track in the announcement
+        self.bot.post_message(announcement).await
+            .map(Some)
+            .map_err(|e| match e.permanent {
+                true => SinkError::fatal(e.message),
+                false => SinkError::retryable(e.message),
+            })
+    }
+}
+
+/// The shape of the json we send to a webhook
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    // A discord webhook message
+    Discord,
+    // Slack style {"text": ...}
+    Slack,
+    // The whole post as we store it
+    Json,
+    // Our own template, see fill_template
+    Template,
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+/// A webhook to send posts to, as read from the secrets file
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub name: String,
+    pub url: String,
+    pub format: WebhookFormat,
+    // Only used with the template format
+    #[serde(default)]
+    pub template: String,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+pub struct WebhookSink {
+    config: WebhookConfig,
+    client: Client,
+}
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> WebhookSink {
+        WebhookSink {
+            config: config,
+            client: Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build().expect("Error building webhook client"),
+        }
+    }
+
+    fn body(&self, post: &SnifferPost) -> String {
+        let link = post.permalink.clone().or(post.url.clone()).unwrap_or_default();
+        // Only discord wants our discord markup, everyone else gets the body as reddit had it
+        let mut plain = post.clone();
+        plain.body = plain.raw_body.clone().or(plain.body);
+        match self.config.format {
+            WebhookFormat::Discord => {
+                let mut content: String = post.discord_string().chars().take(DISCORD_WEBHOOK_LIMIT.saturating_sub(link.len() + 3)).collect();
+                content.push_str(&format!("\n<{}>", link));
+                json!({ "content": content, "username": "FeO sniffer" }).to_string()
+            }
+            WebhookFormat::Slack => {
+                json!({ "text": format!("*{}*\n{}\n/r/{} <{}>", post.title, plain.body.as_deref().unwrap_or_default(), post.subreddit, link) }).to_string()
+            }
+            WebhookFormat::Json => serde_json::to_string(&plain).unwrap_or_default(),
+            WebhookFormat::Template => fill_template(&self.config.template, &plain),
+        }
+    }
+}
+
+#[async_trait]
+impl OutputSink for WebhookSink {
+    async fn deliver(&self, announcement: &mut Announcement) -> Result<Option<Posted>, SinkError> {
+        let result = self.client.post(&self.config.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(self.body(&announcement.post))
+            .send().await
+            .map_err(|e| SinkError::retryable(e.to_string()))?;
+        let status = result.status();
+        if status.is_success() {
+            return Ok(None);
+        }
+        // Server trouble and rate limits might clear up, anything else is on us
+        let message = format!("{} responded with {}", self.config.name, status);
+        match status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            true => Err(SinkError::retryable(message)),
+            false => Err(SinkError::fatal(message)),
+        }
+    }
+
+    fn retries(&self) -> u32 {
+        self.config.retries
+    }
+}
+
+// Swap {title}, {body}, {subreddit}, {author}, {url}, {permalink}, {id} and {timestamp} for the
+// post's values. They get json escaped, since templates are meant to be json
+fn fill_template(template: &str, post: &SnifferPost) -> String {
+    let escape = |s: &str| {
+        let quoted = serde_json::to_string(s).unwrap_or_default();
+        quoted[1..quoted.len() - 1].to_string()
+    };
+    template
+        .replace("{title}", &escape(&post.title))
+        .replace("{body}", &escape(post.body.as_deref().unwrap_or_default()))
+        .replace("{subreddit}", &escape(&post.subreddit))
+        .replace("{author}", &escape(&post.author))
+        .replace("{url}", &escape(post.url.as_deref().unwrap_or_default()))
+        .replace("{permalink}", &escape(post.permalink.as_deref().unwrap_or_default()))
+        .replace("{id}", &escape(&post.fullname()))
+        .replace("{timestamp}", &post.timestamp.to_string())
+}
+
+// What we write down about a post we gave up on
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    sink: &'a str,
+    at: u64,
+    error: &'a str,
+    post: &'a SnifferPost,
+}
+
+/// Every sink we know about by name, handing posts to whichever ones their source picked
+#[derive(Clone)]
+pub struct Outputs {
+    sinks: HashMap<String, Arc<dyn OutputSink>>,
+    dead_letters: PathBuf,
+}
+
+impl Outputs {
+    pub fn new(discord_bot: DiscordBot, webhooks: Vec<WebhookConfig>, dead_letters: PathBuf) -> Outputs {
+        let mut sinks = HashMap::<String, Arc<dyn OutputSink>>::new();
+        sinks.insert(String::from(DISCORD_SINK), Arc::new(DiscordSink { bot: discord_bot }));
+        for webhook in webhooks {
+            if webhook.name == DISCORD_SINK {
+                error!("Webhook can't be named {}, that's the bot's, skipping it", DISCORD_SINK);
+                continue;
+            }
+            sinks.insert(webhook.name.clone(), Arc::new(WebhookSink::new(webhook)));
+        }
+        Outputs {
+            sinks: sinks,
+            dead_letters: dead_letters,
+        }
+    }
+
+    /// The names of every sink, each of which works through the outbox on its own
+    pub fn names(&self) -> Vec<String> {
+        self.sinks.keys().cloned().collect()
+    }
+
+    /// Make one try at sending a post to a sink. Trying again is up to the caller, so a sink
+    /// that's down doesn't hold up the others
+    pub async fn deliver(&self, name: &str, announcement: &mut Announcement) -> Result<Option<Posted>, SinkError> {
+        match self.sinks.get(name) {
+            Some(sink) => sink.deliver(announcement).await,
+            None => Err(SinkError::fatal(format!("No sink named {}", name))),
+        }
+    }
+
+    /// How many times a sink gets to try again before a post is dead lettered
+    pub fn retries(&self, name: &str) -> u32 {
+        self.sinks.get(name).map(|s| s.retries()).unwrap_or(0)
+    }
+
+    /// Keep posts we couldn't deliver around, so they can be sent by hand
+    pub fn dead_letter(&self, sink: &str, error: &str, post: &SnifferPost) {
+        let letter = DeadLetter {
+            sink: sink,
+            at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            error: error,
+            post: post,
+        };
+        let written = serde_json::to_string(&letter).map_err(|e| e.to_string()).and_then(|line| {
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.dead_letters).map_err(|e| e.to_string())?;
+            writeln!(file, "{}", line).map_err(|e| e.to_string())
+        });
+        if let Err(e) = written {
+            error!("Couldn't write dead letter to {}: {}", self.dead_letters.display(), e);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// Turning score samples into something worth reading in discord
+use chrono::{TimeZone, Utc};
+
+use crate::reddit::SnifferPost;
+
+const SPARKS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// How many posts make the weekly digest
+const DIGEST_SIZE: usize = 10;
+pub const WEEK: u64 = 7 * 24 * 60 * 60;
+
+/// A little bar chart of some numbers, scaled between their min and max
+pub fn sparkline(values: &[i64]) -> String {
+    let min = values.iter().copied().min().unwrap_or(0);
+    let max = values.iter().copied().max().unwrap_or(0);
+    let range = (max - min).max(1) as f64;
+    values.iter()
+        .map(|v| SPARKS[(((v - min) as f64 / range) * (SPARKS.len() - 1) as f64).round() as usize])
+        .collect()
+}
+
+/// How a single post is doing, and how it got there
+pub fn post_report(post: &SnifferPost) -> String {
+    let mut text = format!(
+        "**{}**\n/r/{} by /u/{}\nScore **{}** ({:.0}% upvoted), **{}** comments",
+        post.title, post.subreddit, post.author, post.score, post.upvote_ratio * 100.0, post.num_comments
+    );
+    if post.samples.len() > 1 {
+        let first = post.samples.first().unwrap();
+        let last = post.samples.last().unwrap();
+        let hours = last.at.saturating_sub(first.at) as f64 / 3600.0;
+        let scores: Vec<i64> = post.samples.iter().map(|s| s.score).collect();
+        let comments: Vec<i64> = post.samples.iter().map(|s| s.num_comments).collect();
+        text.push_str(&format!(
+            "\nOver {:.1}h:\n`score    {} {} -> {}`\n`comments {} {} -> {}`",
+            hours, sparkline(&scores), first.score, last.score,
+            sparkline(&comments), first.num_comments, last.num_comments
+        ));
+    }
+    else {
+        text.push_str("\nNot enough samples yet to show a trend");
+    }
+    if let Some(link) = &post.permalink {
+        text.push_str(&format!("\n<{}>", link));
+    }
+    text
+}
+
+/// The best posts out of the given ones
+pub fn digest(mut posts: Vec<SnifferPost>, since: u64) -> String {
+    posts.retain(|p| !p.is_comment() && p.deleted_at.is_none());
+    posts.sort_by(|a, b| b.score.cmp(&a.score));
+    let date = |t: u64| Utc.timestamp_opt(t as i64, 0).single().map(|d| d.format("%b %d").to_string()).unwrap_or_default();
+    let mut text = format!("**Top posts since {}**", date(since));
+    if posts.is_empty() {
+        text.push_str("\nNothing, apparently");
+    }
+    for (i, post) in posts.iter().take(DIGEST_SIZE).enumerate() {
+        let title: String = post.title.chars().take(100).collect();
+        let line = format!(
+            "\n{}. **{}** ({} points, {} comments) /r/{}\n<{}>",
+            i + 1, title, post.score, post.num_comments, post.subreddit,
+            post.permalink.as_deref().unwrap_or_default()
+        );
+        // Stay under discord's message limit
+        if text.chars().count() + line.chars().count() > 1900 {
+            break;
+        }
+        text.push_str(&line);
+    }
+    text
+}
+
+/// When the next weekly digest is due, mondays at midnight utc
+pub fn next_digest_at(now: u64) -> u64 {
+    // The epoch was a thursday, so mondays are 4 days off of whole weeks
+    let monday = 4 * 24 * 60 * 60;
+    ((now.saturating_sub(monday) / WEEK) + 1) * WEEK + monday
+}